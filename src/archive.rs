@@ -0,0 +1,266 @@
+// Archive contents: the members of tar (plain or gzipped) and zip
+// files, read in place without extracting anything.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::NaiveDate;
+use tar::EntryType;
+
+use crate::hash::{self, HashAlgo, Throttle, Throttled};
+use crate::Kind;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+
+impl Format {
+    /// Recognise an archive by the end of its name.
+    pub fn of(path: &Path) -> Option<Format> {
+        let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+        if name.ends_with(".tar") {
+            Some(Format::Tar)
+        }
+        else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Format::TarGz)
+        }
+        else if name.ends_with(".zip") {
+            Some(Format::Zip)
+        }
+        else {
+            None
+        }
+    }
+}
+
+
+/// One archive member, with as much as the archive records about it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Member {
+    /// Path within the archive, without a leading `./` or trailing `/`.
+    pub path: String,
+    pub kind: Kind,
+    pub size: u64,
+    /// `st_mode` style: file type and permission bits.
+    pub mode: Option<u32>,
+    pub mtime: Option<SystemTime>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    pub target: Option<String>,
+    /// Full hex digest, for the files `hash_if` chose.
+    pub digest: Option<String>,
+}
+
+
+/// Every member of the archive at `path`, in archive order, hashing
+/// the regular files whose size `hash_if` accepts (just their first
+/// `limit` bytes, if given).  With a throttle, the whole archive is
+/// read at its pace, as it all comes off the disk.
+pub fn members(path: &Path, format: Format, algo: HashAlgo, chunk: usize, limit: Option<u64>,
+               throttle: Option<&Throttle>, hash_if: impl Fn(u64) -> bool) -> io::Result<Vec<Member>> {
+    let file = File::open(path)?;
+    let limit = limit.unwrap_or(u64::MAX);
+    match throttle {
+        Some(throttle) => read_members(Throttled { inner: file, throttle }, format, algo, chunk, limit, hash_if),
+        None => read_members(file, format, algo, chunk, limit, hash_if),
+    }
+}
+
+
+fn read_members(file: impl Read + Seek, format: Format, algo: HashAlgo, chunk: usize, limit: u64,
+                hash_if: impl Fn(u64) -> bool) -> io::Result<Vec<Member>> {
+    match format {
+        Format::Tar => tar_members(BufReader::new(file), algo, chunk, limit, hash_if),
+        Format::TarGz => tar_members(flate2::read::GzDecoder::new(BufReader::new(file)), algo, chunk, limit, hash_if),
+        Format::Zip => zip_members(file, algo, chunk, limit, hash_if),
+    }
+}
+
+
+fn tar_members(input: impl Read, algo: HashAlgo, chunk: usize, limit: u64,
+               hash_if: impl Fn(u64) -> bool) -> io::Result<Vec<Member>> {
+    let mut archive = tar::Archive::new(input);
+    let mut members = Vec::new();
+
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let header = entry.header();
+        let entry_type = header.entry_type();
+        let (kind, type_bits) = match entry_type {
+            // pax headers describe the next member rather than being one
+            EntryType::XGlobalHeader | EntryType::XHeader => continue,
+            EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => (Kind::File, 0o100000),
+            EntryType::Directory => (Kind::Dir, 0o040000),
+            EntryType::Symlink => (Kind::Symlink, 0o120000),
+            EntryType::Char => (Kind::Special, 0o020000),
+            EntryType::Block => (Kind::Special, 0o060000),
+            EntryType::Fifo => (Kind::Special, 0o010000),
+            _ => (Kind::Special, 0),
+        };
+
+        let mut member = Member {
+            path: member_path(&entry.path()?.to_string_lossy()),
+            kind,
+            size: if kind == Kind::File { entry.size() } else { 0 },
+            mode: header.mode().ok().map(|mode| mode & 0o7777 | type_bits),
+            mtime: header.mtime().ok().map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+            uid: header.uid().ok().and_then(|id| id.try_into().ok()),
+            gid: header.gid().ok().and_then(|id| id.try_into().ok()),
+            owner: header.username().ok().flatten().filter(|name| !name.is_empty()).map(String::from),
+            group: header.groupname().ok().flatten().filter(|name| !name.is_empty()).map(String::from),
+            target: None,
+            digest: None,
+        };
+        if kind == Kind::Symlink {
+            member.target = entry.link_name()?.map(|target| target.to_string_lossy().into_owned());
+        }
+        if kind == Kind::File && hash_if(member.size) {
+            member.digest = Some(hash::hash_chunked(entry.take(limit), algo, chunk)?.0);
+        }
+        members.push(member);
+    }
+    Ok(members)
+}
+
+
+fn zip_members(file: impl Read + Seek, algo: HashAlgo, chunk: usize, limit: u64,
+               hash_if: impl Fn(u64) -> bool) -> io::Result<Vec<Member>> {
+    let mut archive = zip::ZipArchive::new(BufReader::new(file))?;
+    let mut members = Vec::new();
+
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let (kind, type_bits) = if file.is_dir() {
+            (Kind::Dir, 0o040000)
+        }
+        else if file.is_symlink() {
+            (Kind::Symlink, 0o120000)
+        }
+        else {
+            (Kind::File, 0o100000)
+        };
+
+        let mut member = Member {
+            path: member_path(&file.name()?),
+            kind,
+            size: if kind == Kind::File { file.size() } else { 0 },
+            // archives made off Unix only record the type
+            mode: file.unix_mode().map(|mode| mode & 0o7777 | type_bits),
+            mtime: file.last_modified().and_then(|time| {
+                let date = NaiveDate::from_ymd_opt(time.year().into(), time.month().into(), time.day().into())?;
+                let time = date.and_hms_opt(time.hour().into(), time.minute().into(), time.second().into())?;
+                Some(time.and_utc().into())
+            }),
+            uid: None,
+            gid: None,
+            owner: None,
+            group: None,
+            target: None,
+            digest: None,
+        };
+        match kind {
+            // a link's contents are its target
+            Kind::Symlink => {
+                let mut target = Vec::new();
+                file.read_to_end(&mut target)?;
+                member.target = Some(String::from_utf8_lossy(&target).into_owned());
+            }
+            Kind::File if hash_if(member.size) => {
+                member.digest = Some(hash::hash_chunked(file.take(limit), algo, chunk)?.0);
+            }
+            _ => {}
+        }
+        members.push(member);
+    }
+    Ok(members)
+}
+
+
+/// A member's name as stored, tidied: `./a/b/` becomes `a/b`.
+fn member_path(name: &str) -> String {
+    let name = name.strip_prefix("./").unwrap_or(name);
+    name.trim_end_matches('/').into()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn formats() {
+        assert_eq!(Format::of(Path::new("a/b.tar")), Some(Format::Tar));
+        assert_eq!(Format::of(Path::new("b.TGZ")), Some(Format::TarGz));
+        assert_eq!(Format::of(Path::new("b.tar.gz")), Some(Format::TarGz));
+        assert_eq!(Format::of(Path::new("b.zip")), Some(Format::Zip));
+        assert_eq!(Format::of(Path::new("b.gz")), None);
+        assert_eq!(member_path("./a/b/"), "a/b");
+    }
+
+    #[test]
+    fn tar_and_zip() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let tar_path = tmp.path().join("t.tar.gz");
+        let gz = flate2::write::GzEncoder::new(File::create(&tar_path).unwrap(), flate2::Compression::default());
+        let mut tar = tar::Builder::new(gz);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(6);
+        header.set_mode(0o640);
+        header.set_mtime(1656676800);
+        tar.append_data(&mut header, "./sub/a.txt", &b"hello\n"[..]).unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_size(0);
+        tar.append_link(&mut header, "l", "sub/a.txt").unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+
+        let found = members(&tar_path, Format::TarGz, HashAlgo::Md5, 4, None, None, |_| true).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].path, "sub/a.txt");
+        assert_eq!(found[0].mode, Some(0o100640));
+        assert_eq!(found[0].digest.as_deref(), Some("b1946ac92492d2347c6235b4d2611184"));
+        assert_eq!(found[0].mtime, Some(UNIX_EPOCH + Duration::from_secs(1656676800)));
+        assert_eq!(found[1].kind, Kind::Symlink);
+        assert_eq!(found[1].target.as_deref(), Some("sub/a.txt"));
+
+        // paced by the archive's bytes, read in one go here, starting with nothing in the bucket
+        let throttle = Throttle::new(std::fs::metadata(&tar_path).unwrap().len() * 5);
+        let started = std::time::Instant::now();
+        let paced = members(&tar_path, Format::TarGz, HashAlgo::Md5, 4, None, Some(&throttle), |_| true).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(190));
+        assert_eq!(paced, found);
+
+        let zip_path = tmp.path().join("z.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default().unix_permissions(0o600);
+        zip.add_directory("d/", options).unwrap();
+        zip.start_file("d/big", options).unwrap();
+        zip.write_all(&[7; 1000]).unwrap();
+        zip.finish().unwrap();
+
+        let found = members(&zip_path, Format::Zip, HashAlgo::Md5, 64, None, None, |size| size < 100).unwrap();
+        let kinds: Vec<(&str, Kind, u64)> = found.iter().map(|m| (m.path.as_str(), m.kind, m.size)).collect();
+        assert_eq!(kinds, [("d", Kind::Dir, 0), ("d/big", Kind::File, 1000)]);
+        assert_eq!(found[1].mode, Some(0o100600));
+        assert_eq!(found[1].digest, None);
+
+        // md5 of the first 10 sevens
+        let found = members(&zip_path, Format::Zip, HashAlgo::Md5, 64, Some(10), None, |_| true).unwrap();
+        assert_eq!(found[1].digest.as_deref(), Some(hash::hash_chunked(&[7; 10][..], HashAlgo::Md5, 64).unwrap().0.as_str()));
+    }
+
+}
+
+
+// EOF
@@ -0,0 +1,179 @@
+// Comparison against an earlier scan saved with `--format json` (or
+// ndjson), matching entries by their path relative to the root.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{HashAlgo, Kind, ScanEntry};
+
+
+/// How an entry differs from the baseline.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Added,
+    Removed,
+    /// Size, hash, mode, mtime, kind or link target differ.
+    Modified,
+    Unchanged,
+}
+
+
+impl Change {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Change::Added => "added",
+            Change::Removed => "removed",
+            Change::Modified => "modified",
+            Change::Unchanged => "unchanged",
+        }
+    }
+}
+
+
+/// A regular file's digest from a baseline, good for as long as the
+/// file keeps the size and mtime it had then.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KnownDigest {
+    pub size: u64,
+    /// The mtime in nanoseconds since the epoch.
+    pub mtime_ns: i64,
+    pub algo: HashAlgo,
+    pub hash: String,
+}
+
+
+/// A previous scan's entries, in their original order, noting which
+/// have been matched by the current scan.
+pub struct Baseline {
+    entries: Vec<ScanEntry>,
+    index: HashMap<String, usize>,
+    seen: Vec<bool>,
+}
+
+
+impl Baseline {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(Self::from_json(&std::fs::read_to_string(path)?)?)
+    }
+
+
+    /// Parse either a JSON array of entries or one entry per line.
+    pub fn from_json(text: &str) -> serde_json::Result<Self> {
+        let entries: Vec<ScanEntry> = if text.trim_start().starts_with('[') {
+            serde_json::from_str(text)?
+        }
+        else {
+            text.lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(serde_json::from_str)
+                .collect::<Result<_, _>>()?
+        };
+
+        let index = entries.iter().enumerate()
+            .map(|(i, entry)| (entry.path.clone(), i))
+            .collect();
+        let seen = vec![false; entries.len()];
+        Ok(Self { entries, index, seen })
+    }
+
+
+    /// Classify `entry`, and mark its baseline counterpart as seen.
+    pub fn compare(&mut self, entry: &ScanEntry) -> Change {
+        let i = match self.index.get(&entry.path) {
+            Some(&i) => i,
+            None => return Change::Added,
+        };
+        self.seen[i] = true;
+
+        let old = &self.entries[i];
+        let same = old.size == entry.size
+            && old.hash == entry.hash
+            && old.mode == entry.mode
+            && old.mtime == entry.mtime
+            && old.kind == entry.kind
+            && old.target == entry.target;
+        if same { Change::Unchanged } else { Change::Modified }
+    }
+
+
+    /// The digests of the baseline's regular files, by path, for the
+    /// scanner to reuse rather than read unchanged files again.  Entries
+    /// saved without an exact mtime or the algorithm, as by versions
+    /// before these were recorded, are left out.
+    pub fn digests(&self) -> HashMap<String, KnownDigest> {
+        self.entries.iter()
+            .filter(|entry| entry.kind == Kind::File && entry.has_digest())
+            .filter_map(|entry| Some((entry.path.clone(), KnownDigest {
+                size: entry.size,
+                mtime_ns: entry.mtime_ns?,
+                algo: entry.hash_algo?,
+                hash: entry.hash.clone(),
+            })))
+            .collect()
+    }
+
+
+    /// Baseline entries nothing in the current scan matched, in their
+    /// original order.  Their `name` is the whole relative path.
+    pub fn removed(&self) -> impl Iterator<Item = ScanEntry> + '_ {
+        self.entries.iter().zip(&self.seen)
+            .filter(|(_, seen)| !**seen)
+            .map(|(entry, _)| ScanEntry {
+                name: entry.path.clone(),
+                change: Some(Change::Removed),
+                ..entry.clone()
+            })
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn changes() {
+        let old = r#"[{"path":"a","size":3,"mode":"-rw-r--r--","owner":"root","group":"root","mtime":"2022-07-01T12:00","mtime_ns":1656676800123456789,"hash":"d41d8cd9","hash_algo":"md5","target":null,"kind":"file"}
+,{"path":"gone","size":0,"mode":"drwxr-xr-x","owner":"root","group":"root","mtime":"","hash":"","target":null,"kind":"dir"}
+]"#;
+        let mut baseline = Baseline::from_json(old).unwrap();
+
+        let mut a = baseline.entries[0].clone();
+        assert_eq!(baseline.compare(&a), Change::Unchanged);
+        a.hash = "00000000".into();
+        assert_eq!(baseline.compare(&a), Change::Modified);
+        a.path = "new".into();
+        assert_eq!(baseline.compare(&a), Change::Added);
+
+        let removed: Vec<ScanEntry> = baseline.removed().collect();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].name, "gone");
+        assert_eq!(removed[0].change, Some(Change::Removed));
+
+        let digests = baseline.digests();
+        assert_eq!(digests.len(), 1);
+        assert_eq!(digests["a"], KnownDigest {
+            size: 3, mtime_ns: 1656676800123456789, algo: HashAlgo::Md5, hash: "d41d8cd9".into(),
+        });
+
+        // saved without the exact mtime and algorithm to check a digest by
+        let older = r#"[{"path":"a","size":3,"mode":"-rw-r--r--","owner":"root","group":"root","mtime":"2022-07-01T12:00","hash":"d41d8cd9","target":null,"kind":"file"}]"#;
+        assert!(Baseline::from_json(older).unwrap().digests().is_empty());
+    }
+
+    #[test]
+    fn ndjson() {
+        let text = "{\"path\":\"a\",\"size\":1,\"mode\":null,\"owner\":\"\",\"group\":\"\",\"mtime\":\"?\",\"hash\":\"\",\"target\":null,\"kind\":\"special\",\"notes\":[\"fifo\"]}\n\n";
+        let baseline = Baseline::from_json(text).unwrap();
+        assert_eq!(baseline.entries.len(), 1);
+        assert_eq!(baseline.entries[0].notes, ["fifo"]);
+    }
+
+}
+
+
+// EOF
@@ -0,0 +1,113 @@
+// File capabilities: decoding the `security.capability` xattr into the
+// text form `getcap` uses, e.g. `cap_net_bind_service+ep`.
+
+
+/// Capability names by number, as in `linux/capability.h`.
+const NAMES: [&str; 41] = [
+    "cap_chown", "cap_dac_override", "cap_dac_read_search", "cap_fowner",
+    "cap_fsetid", "cap_kill", "cap_setgid", "cap_setuid",
+    "cap_setpcap", "cap_linux_immutable", "cap_net_bind_service", "cap_net_broadcast",
+    "cap_net_admin", "cap_net_raw", "cap_ipc_lock", "cap_ipc_owner",
+    "cap_sys_module", "cap_sys_rawio", "cap_sys_chroot", "cap_sys_ptrace",
+    "cap_sys_pacct", "cap_sys_admin", "cap_sys_boot", "cap_sys_nice",
+    "cap_sys_resource", "cap_sys_time", "cap_sys_tty_config", "cap_mknod",
+    "cap_lease", "cap_audit_write", "cap_audit_control", "cap_setfcap",
+    "cap_mac_override", "cap_mac_admin", "cap_syslog", "cap_wake_alarm",
+    "cap_block_suspend", "cap_audit_read", "cap_perfmon", "cap_bpf",
+    "cap_checkpoint_restore",
+];
+
+const REVISION_MASK: u32 = 0xff000000;
+const FLAG_EFFECTIVE: u32 = 0x000001;
+
+
+/// Decode a `vfs_cap_data` value, or None if it isn't one.  Capabilities
+/// sharing the same flags are grouped: `cap_net_admin,cap_net_raw+ep`.
+pub fn decode(value: &[u8]) -> Option<String> {
+    let word = |i: usize| -> Option<u32> {
+        let bytes = value.get(i*4..i*4 + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?))
+    };
+
+    let magic = word(0)?;
+    // revision 1 has 32 bits of capabilities; 2 and 3 have 64 (3 adds a root uid)
+    let words = match magic & REVISION_MASK {
+        0x01000000 if value.len() == 12 => 1,
+        0x02000000 if value.len() == 20 => 2,
+        0x03000000 if value.len() == 24 => 2,
+        _ => return None,
+    };
+    let effective = magic & FLAG_EFFECTIVE != 0;
+
+    let mut permitted = 0u64;
+    let mut inheritable = 0u64;
+    for i in 0..words {
+        permitted |= (word(1 + 2*i)? as u64) << (32 * i);
+        inheritable |= (word(2 + 2*i)? as u64) << (32 * i);
+    }
+
+    // (flags, names) in order of each group's lowest capability
+    let mut groups: Vec<(String, Vec<String>)> = Vec::new();
+    for cap in 0..64 {
+        let p = permitted & (1 << cap) != 0;
+        let i = inheritable & (1 << cap) != 0;
+        if !p && !i {
+            continue;
+        }
+
+        let mut flags = String::new();
+        if effective { flags.push('e'); }
+        if i { flags.push('i'); }
+        if p { flags.push('p'); }
+        let name = match NAMES.get(cap) {
+            Some(name) => name.to_string(),
+            None => format!("cap_{}", cap),
+        };
+
+        match groups.iter_mut().find(|(f, _)| *f == flags) {
+            Some((_, names)) => names.push(name),
+            None => groups.push((flags, vec![name])),
+        }
+    }
+
+    if groups.is_empty() {
+        return None;
+    }
+    let text: Vec<String> = groups.iter()
+        .map(|(flags, names)| format!("{}+{}", names.join(","), flags))
+        .collect();
+    Some(text.join(" "))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn data(magic: u32, words: &[u32]) -> Vec<u8> {
+        std::iter::once(magic).chain(words.iter().copied())
+            .flat_map(u32::to_le_bytes)
+            .collect()
+    }
+
+    #[test]
+    fn decoding() {
+        let bind = data(0x02000001, &[1 << 10, 0, 0, 0]);
+        assert_eq!(decode(&bind).as_deref(), Some("cap_net_bind_service+ep"));
+
+        let net = data(0x02000000, &[(1 << 12) | (1 << 13) | 1, 1, 1 << 6, 0]);
+        assert_eq!(decode(&net).as_deref(), Some("cap_chown+ip cap_net_admin,cap_net_raw,cap_perfmon+p"));
+
+        // revision 3 carries a root uid after the capabilities
+        let v3 = data(0x03000001, &[1 << 21, 0, 0, 0, 1000]);
+        assert_eq!(decode(&v3).as_deref(), Some("cap_sys_admin+ep"));
+
+        assert_eq!(decode(&data(0x02000001, &[0, 0, 0, 0])), None);
+        assert_eq!(decode(&bind[..10]), None);
+        assert_eq!(decode(b""), None);
+    }
+
+}
+
+
+// EOF
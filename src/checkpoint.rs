@@ -0,0 +1,114 @@
+// Progress through a long scan, saved as it goes so an interrupted one
+// can pick up where it left off: the roots finished, and under the
+// others, the top-level directories whose whole subtrees were reported.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+
+/// One root's progress.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct RootProgress {
+    /// The whole root was scanned.
+    done: bool,
+    /// Top-level directories, relative to the root, scanned in full.
+    dirs: BTreeSet<String>,
+}
+
+
+/// What's been scanned, by root as given.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Checkpoint {
+    roots: BTreeMap<String, RootProgress>,
+    /// Anything finished since the last save.
+    #[serde(skip)]
+    changed: bool,
+}
+
+
+impl Checkpoint {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+
+    /// Write the checkpoint to `path` by way of a temporary file beside
+    /// it, so an interruption mid-write leaves the last one whole.
+    pub fn save(&mut self, path: &Path) -> io::Result<()> {
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        std::fs::write(&temp, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&temp, path)?;
+        self.changed = false;
+        Ok(())
+    }
+
+
+    /// Whether anything's been finished since the last save.
+    pub fn changed(&self) -> bool {
+        self.changed
+    }
+
+
+    pub fn root_done(&self, root: &Path) -> bool {
+        self.roots.get(&*root.to_string_lossy()).is_some_and(|progress| progress.done)
+    }
+
+
+    /// Whether `dir`, relative to `root`, was scanned in full.
+    pub fn dir_done(&self, root: &Path, dir: &Path) -> bool {
+        self.roots.get(&*root.to_string_lossy())
+            .is_some_and(|progress| progress.dirs.contains(&*dir.to_string_lossy()))
+    }
+
+
+    pub(crate) fn finish_root(&mut self, root: &Path) {
+        self.progress(root).done = true;
+    }
+
+
+    pub(crate) fn finish_dir(&mut self, root: &Path, dir: &Path) {
+        let dir = dir.to_string_lossy().into_owned();
+        self.progress(root).dirs.insert(dir);
+    }
+
+
+    fn progress(&mut self, root: &Path) -> &mut RootProgress {
+        self.changed = true;
+        self.roots.entry(root.to_string_lossy().into_owned()).or_default()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("scan.checkpoint");
+
+        let mut checkpoint = Checkpoint::default();
+        assert!(!checkpoint.changed());
+        checkpoint.finish_dir(Path::new("/data"), Path::new("a"));
+        checkpoint.finish_root(Path::new("/home"));
+        assert!(checkpoint.changed());
+        checkpoint.save(&path).unwrap();
+        assert!(!checkpoint.changed());
+
+        let loaded = Checkpoint::load(&path).unwrap();
+        assert!(loaded.dir_done(Path::new("/data"), Path::new("a")));
+        assert!(!loaded.dir_done(Path::new("/data"), Path::new("b")));
+        assert!(!loaded.root_done(Path::new("/data")));
+        assert!(loaded.root_done(Path::new("/home")));
+        assert!(!loaded.changed());
+    }
+
+}
+
+
+// EOF
@@ -0,0 +1,134 @@
+// Duplicate detection: files are grouped by size first, and only those
+// sharing a size with another are read and hashed in full.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::PathBuf;
+
+use crate::hash::{self, HashAlgo, Throttle};
+
+
+/// Files with identical contents.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DupeGroup {
+    pub size: u64,
+    /// Full hex digest of the shared contents.
+    pub hash: String,
+    /// In the order they were given.
+    pub paths: Vec<PathBuf>,
+}
+
+
+impl DupeGroup {
+    /// Bytes that would be freed by keeping only one copy.
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+}
+
+
+/// Group `files` (path and size) by content, returning only groups of
+/// two or more, most wasted space first, along with the files that
+/// couldn't be read in full, which are in no group.  Empty files are
+/// ignored.  Big files are mapped with `map`, and reads are paced by
+/// `throttle`, as by `hash::hash_file()`.
+pub fn find_dupes(files: &[(PathBuf, u64)], algo: HashAlgo, jobs: usize, chunk: usize, map: bool,
+                  throttle: Option<&Throttle>) -> (Vec<DupeGroup>, Vec<(PathBuf, io::Error)>) {
+    let mut by_size: BTreeMap<u64, Vec<&PathBuf>> = BTreeMap::new();
+    for (path, size) in files {
+        if *size > 0 {
+            by_size.entry(*size).or_default().push(path);
+        }
+    }
+
+    let candidates: Vec<(u64, PathBuf)> = by_size.into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .flat_map(|(size, paths)| paths.into_iter().map(move |path| (size, path.clone())))
+        .collect();
+    let paths: Vec<PathBuf> = candidates.iter().map(|(_, path)| path.clone()).collect();
+    let digests = hash::hash_files(&paths, algo, jobs, chunk, None, map, throttle);
+
+    // keyed by (size, digest), remembering which came first
+    let mut groups: Vec<DupeGroup> = Vec::new();
+    let mut index: HashMap<(u64, String), usize> = HashMap::new();
+    let mut unread = Vec::new();
+    for ((size, path), hashed) in candidates.into_iter().zip(digests) {
+        // a partial read would only match other files by chance
+        let digest = match hashed {
+            Ok((digest, read)) if read == size => digest,
+            Ok((_, read)) => {
+                let e = io::Error::other(format!("read {} bytes of {}; changed during scan", read, size));
+                unread.push((path, e));
+                continue;
+            }
+            Err(e) => {
+                unread.push((path, e));
+                continue;
+            }
+        };
+        match index.get(&(size, digest.clone())) {
+            Some(&i) => groups[i].paths.push(path),
+            None => {
+                index.insert((size, digest.clone()), groups.len());
+                groups.push(DupeGroup { size, hash: digest, paths: vec![path] });
+            }
+        }
+    }
+
+    groups.retain(|group| group.paths.len() > 1);
+    groups.sort_by(|a, b| b.wasted().cmp(&a.wasted()).then_with(|| a.paths[0].cmp(&b.paths[0])));
+    (groups, unread)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn groups() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut files = Vec::new();
+        for (name, body) in [("a", "hello\n"), ("b", "world\n"), ("c", "hello\n"),
+                             ("d", "big one\n"), ("e", "big one\n"), ("f", "big one\n"),
+                             ("g", ""), ("h", "")] {
+            let path = tmp.path().join(name);
+            std::fs::write(&path, body).unwrap();
+            files.push((path, body.len() as u64));
+        }
+
+        let (groups, unread) = find_dupes(&files, HashAlgo::Md5, 2, hash::DEFAULT_CHUNK, false, None);
+        assert!(unread.is_empty());
+        let names: Vec<Vec<&str>> = groups.iter()
+            .map(|g| g.paths.iter().map(|p| p.file_name().unwrap().to_str().unwrap()).collect())
+            .collect();
+        assert_eq!(names, [vec!["d", "e", "f"], vec!["a", "c"]]);
+        assert_eq!(groups[0].wasted(), 16);
+        assert_eq!(groups[1].hash, "b1946ac92492d2347c6235b4d2611184");
+    }
+
+    #[test]
+    fn unreadable_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut files = Vec::new();
+        for (name, body) in [("a", "aaaa"), ("b", "bbbb"), ("c", "aaaa"), ("d", "dddd")] {
+            let path = tmp.path().join(name);
+            std::fs::write(&path, body).unwrap();
+            files.push((path, 4));
+        }
+        // can't be opened, and shorter than listed
+        std::fs::remove_file(tmp.path().join("a")).unwrap();
+        std::fs::write(tmp.path().join("b"), "bb").unwrap();
+        files.push((tmp.path().join("gone"), 4));
+
+        let (groups, unread) = find_dupes(&files, HashAlgo::Md5, 1, hash::DEFAULT_CHUNK, false, None);
+        assert!(groups.is_empty());
+        let names: Vec<&str> = unread.iter().map(|(p, _)| p.file_name().unwrap().to_str().unwrap()).collect();
+        assert_eq!(names, ["a", "b", "gone"]);
+        assert_eq!(unread[0].1.kind(), io::ErrorKind::NotFound);
+    }
+
+}
+
+
+// EOF
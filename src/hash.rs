@@ -0,0 +1,313 @@
+// Content hashing: one enum over the supported digests, plus the
+// file readers that feed it.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgo {
+    Md5,
+    Sha1,
+    Sha256,
+    Blake3,
+}
+
+
+impl HashAlgo {
+    /// Hex digits in a whole digest.
+    pub fn digest_len(self) -> usize {
+        match self {
+            HashAlgo::Md5 => 32,
+            HashAlgo::Sha1 => 40,
+            HashAlgo::Sha256 | HashAlgo::Blake3 => 64,
+        }
+    }
+}
+
+
+/// Incremental digest over one of the supported algorithms.
+pub enum Hasher {
+    Md5(md5::Context),
+    Sha1(sha1::Sha1),
+    Sha256(sha2::Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+
+impl Hasher {
+    pub fn new(algo: HashAlgo) -> Self {
+        match algo {
+            HashAlgo::Md5 => Hasher::Md5(md5::Context::new()),
+            HashAlgo::Sha1 => Hasher::Sha1(sha1::Sha1::new()),
+            HashAlgo::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            HashAlgo::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+
+    pub fn consume(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Md5(ctx) => ctx.consume(data),
+            Hasher::Sha1(ctx) => ctx.update(data),
+            Hasher::Sha256(ctx) => ctx.update(data),
+            Hasher::Blake3(ctx) => { ctx.update(data); }
+        }
+    }
+
+
+    /// Full digest as lowercase hex.
+    pub fn finalize(self) -> String {
+        match self {
+            Hasher::Md5(ctx) => hex::encode(ctx.compute().0),
+            Hasher::Sha1(ctx) => hex::encode(ctx.finalize()),
+            Hasher::Sha256(ctx) => hex::encode(ctx.finalize()),
+            Hasher::Blake3(ctx) => ctx.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+
+/// Files at least this big are memory-mapped rather than read.
+const MMAP_MIN: u64 = 4 * 1024*1024;
+
+/// Bytes per read for files that aren't mapped, unless told otherwise.
+pub const DEFAULT_CHUNK: usize = 64*1024;
+
+
+/// A cap on how fast file contents are read, shared by every thread
+/// hashing for a scan: a token bucket holding up to a second's worth.
+#[derive(Debug)]
+pub struct Throttle {
+    /// Bytes a second.
+    rate: u64,
+    /// Bytes that may be read now (less than none once overdrawn), as
+    /// of when it was last topped up.
+    bucket: Mutex<(f64, Instant)>,
+}
+
+
+impl Throttle {
+    /// A cap of `rate` bytes a second; 0 is taken as 1.
+    pub fn new(rate: u64) -> Self {
+        Self { rate: rate.max(1), bucket: Mutex::new((0.0, Instant::now())) }
+    }
+
+
+    /// Account for `bytes` just read, pausing as long as it takes for
+    /// the reads so far to come back within the rate.
+    pub fn spend(&self, bytes: u64) {
+        let rate = self.rate as f64;
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let (tokens, since) = &mut *bucket;
+            let now = Instant::now();
+            *tokens = (*tokens + now.duration_since(*since).as_secs_f64() * rate).min(rate) - bytes as f64;
+            *since = now;
+            (*tokens < 0.0).then(|| Duration::from_secs_f64(-*tokens / rate))
+        };
+        if let Some(wait) = wait {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+
+/// Reads from `inner`, paced by a throttle.
+pub(crate) struct Throttled<'t, R> {
+    pub(crate) inner: R,
+    pub(crate) throttle: &'t Throttle,
+}
+
+
+impl<R: Read> Read for Throttled<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.throttle.spend(n as u64);
+        Ok(n)
+    }
+}
+
+
+impl<R: Seek> Seek for Throttled<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+
+/// Hash a file's contents, or just the first `limit` bytes, reading
+/// `chunk` bytes at a time, along with how many bytes went in: an error
+/// if the file can't be opened or a read fails.  With `map`, big files
+/// are memory-mapped instead where they can be, which is faster, but a
+/// file truncated while it's mapped kills the process with SIGBUS.  With
+/// a throttle, even big files are read, so the reads can be paced.
+pub fn hash_file(path: &Path, algo: HashAlgo, chunk: usize, limit: Option<u64>, map: bool,
+                 throttle: Option<&Throttle>) -> io::Result<(String, u64)> {
+    let file = File::open(path)?;
+
+    let mapped = map && file.metadata().is_ok_and(|meta| meta.len() >= MMAP_MIN);
+    if mapped && limit.is_none_or(|limit| limit >= MMAP_MIN) && throttle.is_none() {
+        if let Some(hashed) = hash_mapped(&file, algo, limit) {
+            return Ok(hashed);
+        }
+    }
+    let file = file.take(limit.unwrap_or(u64::MAX));
+    match throttle {
+        Some(throttle) => hash_chunked(Throttled { inner: file, throttle }, algo, chunk),
+        None => hash_chunked(file, algo, chunk),
+    }
+}
+
+
+/// Read `size` bytes at a time, up to the end, returning the digest and
+/// the bytes read, or the first error.
+pub(crate) fn hash_chunked(mut input: impl Read, algo: HashAlgo, size: usize) -> io::Result<(String, u64)> {
+    let mut hasher = Hasher::new(algo);
+    let mut chunk = Vec::with_capacity(size);
+    let mut total = 0;
+    loop {
+        let n = input.by_ref().take(size as u64).read_to_end(&mut chunk)?;
+        hasher.consume(&chunk[..n]);
+        total += n as u64;
+        if n < size { break; }
+        chunk.clear();
+    }
+    Ok((hasher.finalize(), total))
+}
+
+
+/// Map the whole file and hash it, or its first `limit` bytes, in one
+/// go, or None if it can't be mapped (some filesystems and special
+/// files can't).
+fn hash_mapped(file: &File, algo: HashAlgo, limit: Option<u64>) -> Option<(String, u64)> {
+    // SAFETY: the map is only read, and dropped before returning.  If
+    // another process truncates the file meanwhile, reading past its new
+    // end raises SIGBUS; files are only mapped when the caller asked to,
+    // taking that risk for the speed.
+    let map = unsafe { memmap2::Mmap::map(file) }.ok()?;
+    let mut hasher = Hasher::new(algo);
+    let len = limit.map_or(map.len(), |limit| map.len().min(limit as usize));
+    hasher.consume(&map[..len]);
+    Some((hasher.finalize(), len as u64))
+}
+
+
+/// Hash several files on up to `jobs` threads, returning what
+/// `hash_file` does for each, in the same order as `paths`.
+pub fn hash_files(paths: &[PathBuf], algo: HashAlgo, jobs: usize, chunk: usize,
+                  limit: Option<u64>, map: bool, throttle: Option<&Throttle>) -> Vec<io::Result<(String, u64)>> {
+    let jobs = jobs.min(paths.len());
+    if jobs <= 1 {
+        return paths.iter().map(|path| hash_file(path, algo, chunk, limit, map, throttle)).collect();
+    }
+
+    // workers take the next unclaimed path until none are left
+    let next = AtomicUsize::new(0);
+    let mut done: Vec<(usize, io::Result<(String, u64)>)> = std::thread::scope(|s| {
+        let workers: Vec<_> = (0..jobs).map(|_| s.spawn(|| {
+            let mut mine = Vec::new();
+            loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= paths.len() {
+                    break mine;
+                }
+                mine.push((i, hash_file(&paths[i], algo, chunk, limit, map, throttle)));
+            }
+        })).collect();
+
+        workers.into_iter().flat_map(|w| w.join().unwrap()).collect()
+    });
+
+    done.sort_unstable_by_key(|(i, _)| *i);
+    done.into_iter().map(|(_, hashed)| hashed).collect()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Hash of empty string
+    #[test]
+    fn md5() {
+        let x = md5::Context::new();
+        assert_eq!("d41d8cd98f00b204e9800998ecf8427e", format!("{:?}", x.compute()));
+    }
+
+    #[test]
+    fn hasher_empty() {
+        let empty = |algo| Hasher::new(algo).finalize();
+        assert_eq!(empty(HashAlgo::Md5), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(empty(HashAlgo::Sha1), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(empty(HashAlgo::Sha256),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(empty(HashAlgo::Blake3),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262");
+        for algo in [HashAlgo::Md5, HashAlgo::Sha1, HashAlgo::Sha256, HashAlgo::Blake3] {
+            assert_eq!(empty(algo).len(), algo.digest_len());
+        }
+    }
+
+    #[test]
+    fn hasher_chunked() {
+        let mut whole = Hasher::new(HashAlgo::Sha256);
+        whole.consume(b"hello world");
+        let mut parts = Hasher::new(HashAlgo::Sha256);
+        parts.consume(b"hello ");
+        parts.consume(b"world");
+        assert_eq!(whole.finalize(), parts.finalize());
+    }
+
+    #[test]
+    fn mapped_matches_chunked() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("f");
+        let body: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &body).unwrap();
+
+        let open = || File::open(&path).unwrap();
+        for algo in [HashAlgo::Md5, HashAlgo::Blake3] {
+            let mapped = hash_mapped(&open(), algo, None).unwrap();
+            assert_eq!(mapped, hash_chunked(open(), algo, DEFAULT_CHUNK).unwrap());
+            assert_eq!(mapped, hash_chunked(open(), algo, 1000).unwrap());
+            assert_eq!(mapped.1, 200_000);
+            assert_eq!(hash_file(&path, algo, 7, None, true, None).unwrap(), mapped);
+            assert_eq!(hash_file(&path, algo, 7, None, false, None).unwrap(), mapped);
+
+            let head = hash_chunked(&body[..1000], algo, 64).unwrap();
+            assert_eq!(hash_mapped(&open(), algo, Some(1000)).unwrap(), head);
+            assert_eq!(hash_file(&path, algo, 64, Some(1000), true, None).unwrap(), head);
+            assert_eq!(hash_file(&path, algo, 64, Some(1 << 20), true, None).unwrap().1, 200_000);
+        }
+        let gone = hash_file(&tmp.path().join("gone"), HashAlgo::Md5, 7, None, false, None);
+        assert_eq!(gone.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn throttled() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("f");
+        std::fs::write(&path, [1; 20_000]).unwrap();
+
+        // 20 KB at 100 KB/s, starting with nothing in the bucket
+        let throttle = Throttle::new(100_000);
+        let started = Instant::now();
+        let paced = hash_file(&path, HashAlgo::Md5, 4096, None, true, Some(&throttle)).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(190));
+        assert_eq!(paced, hash_file(&path, HashAlgo::Md5, 4096, None, false, None).unwrap());
+    }
+
+}
+
+
+// EOF
@@ -0,0 +1,3087 @@
+// Deterministic filesystem scans.
+//
+// A Scanner walks one or more roots depth first, listing each
+// directory's entries in name order before descending into its
+// subdirectories, and yields the result as a stream of Events.
+// The treescan binary is a formatter over that stream.
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs::Metadata;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local, Utc};
+use clap::ValueEnum;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::{Deserialize, Serialize};
+
+pub mod archive;
+pub mod baseline;
+pub mod caps;
+pub mod checkpoint;
+pub mod dupes;
+pub mod hash;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod platform;
+pub mod prefetch;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod verify;
+
+pub use baseline::{Baseline, Change, KnownDigest};
+pub use checkpoint::Checkpoint;
+pub use dupes::{DupeGroup, find_dupes};
+pub use hash::{HashAlgo, Hasher, Throttle};
+pub use verify::{sum_line, Manifest, Verdict};
+
+use prefetch::{Lister, Prefetch};
+
+
+/// Exclusions kept with a tree, read from the top of each root.
+pub const IGNORE_FILE: &str = ".treescanignore";
+
+
+/// UTC to the minute, e.g. `2022-07-01T12:00`.
+pub const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M";
+
+
+/// The wait before the first retry; it doubles for each after.
+const RETRY_DELAY: Duration = Duration::from_millis(50);
+
+
+/// Settings that affect what gets walked and what is gathered per entry.
+#[derive(Clone, Debug)]
+pub struct ScanOptions {
+    /// Trace the walk on stderr.
+    pub debug: bool,
+    /// Files of this many bytes or more aren't hashed.
+    pub max_sum_size: u64,
+    /// Hex digits of the digest to keep.
+    pub hash_len: usize,
+    pub hash: HashAlgo,
+    /// Never read file contents.
+    pub no_hash: bool,
+    /// Never open files at all: no hashing, and no looking inside
+    /// archives.  With `debug`, say which files would have been hashed.
+    pub stat_only: bool,
+    /// Hash files of any size, ignoring `max_sum_size`.
+    pub full_hash: bool,
+    /// Give empty files the digest of no bytes, as `md5sum` would,
+    /// rather than `empty`.
+    pub hash_empty: bool,
+    /// Hash just the first this many bytes of each file, whatever its
+    /// size, marking the digests with a leading `~`.
+    pub head_hash: Option<u64>,
+    /// Threads hashing each directory's files; 1 hashes in line.
+    pub jobs: usize,
+    /// Threads reading directory listings ahead of the walk; 1 reads
+    /// each as it's reached.  The output is the same either way.
+    pub threads: usize,
+    /// Roots scanned at once, each on its own thread with its events
+    /// held until it's done, then handed out in the order the roots
+    /// were added.  More than one can't be combined with `max_files`.
+    pub root_jobs: usize,
+    /// Bytes per read when hashing files that aren't mapped.
+    pub read_chunk: usize,
+    /// Memory-map files of 4M or more to hash them, rather than reading
+    /// them: faster, but a file truncated while it's mapped kills the
+    /// process with SIGBUS, so only for trees nothing else is writing to.
+    pub mmap: bool,
+    /// Globs, relative to the root, for paths to skip entirely.
+    pub exclude: Vec<String>,
+    /// Globs, relative to the root, for entries to report; everything
+    /// if empty.  Directories are still descended.
+    pub include: Vec<String>,
+    /// Skip whatever `.gitignore` files found along the walk, and the
+    /// user's global excludes file, as Git would.
+    pub gitignore: bool,
+    /// Skip entries whose names start with a dot, and all beneath them.
+    /// Roots are scanned whatever their names.
+    pub skip_hidden: bool,
+    /// Match the include and exclude globs, ignore files' rules, and
+    /// owner and group names without regard to case.
+    pub ignore_case: bool,
+    /// Skip what a `.treescanignore` file (gitignore syntax) at the top
+    /// of each root matches, along with `exclude`.
+    pub ignore_file: bool,
+    /// Deepest level reported, walkdir style: the root's own contents
+    /// are level 1.
+    pub max_depth: Option<u32>,
+    /// Only report regular files of at least this many bytes.
+    pub min_size: Option<u64>,
+    /// Only report regular files of at most this many bytes.
+    pub max_size: Option<u64>,
+    /// Only report regular files modified at or after this time.
+    pub since: Option<SystemTime>,
+    /// Only report regular files modified at or before this time.
+    pub until: Option<SystemTime>,
+    /// Only report entries owned by this user, by name or numeric uid.
+    /// Directories are still descended.
+    pub owner: Option<String>,
+    /// Only report entries whose group is this, by name or numeric gid.
+    pub group: Option<String>,
+    /// Only report entries whose permission bits, masked with this,
+    /// equal `mode_match`, or if that's not given, have every bit of
+    /// the mask set: 0o002 for world-writable, 0o4000 for setuid.
+    pub mode_mask: Option<u32>,
+    pub mode_match: Option<u32>,
+    /// Try failed stats, listings and reads this many more times, with a
+    /// growing pause between, before giving up on them: for flaky
+    /// network mounts.  Missing and forbidden paths aren't retried.
+    pub retries: u32,
+    /// Read file contents at no more than this many bytes a second, all
+    /// threads together.  Metadata isn't held back.
+    pub limit_rate: Option<u64>,
+    /// Stop the whole scan, cleanly, rather than report more entries
+    /// than this.
+    pub max_files: Option<u64>,
+    /// Only report zero-length files and directories with nothing in
+    /// them (not even excluded entries).  Everything is still descended.
+    pub empty: bool,
+    /// Descend into mountpoints too; they're still marked as such.
+    pub cross_device: bool,
+    /// Mark mountpoints with their filesystem's type and source, such as
+    /// `mountpoint: ext4 on /dev/sda1`, where the system says (Linux).
+    pub resolve_mountpoints: bool,
+    /// Descend into symlinked directories and hash symlinked files by
+    /// their targets' contents.
+    pub follow_symlinks: bool,
+    /// Without `follow_symlinks`, still descend into the symlinked
+    /// directories whose path (relative to the root) or target, as
+    /// written, matches one of these globs.
+    pub follow_only: Vec<String>,
+    /// Count a multiply-linked file's bytes only at its first path.
+    pub dedup_hardlinks: bool,
+    /// Show and total the space allocated on disk rather than apparent
+    /// sizes, where the platform says.
+    pub blocks: bool,
+    /// Count a symlink's own size (its target's length) in the totals.
+    /// Followed links to files always count their target's size.
+    pub count_symlinks: bool,
+    /// Note what each symlink resolves to, or that it's dangling.
+    pub deref_info: bool,
+    /// List the members of tar and zip files after them, as
+    /// `archive.zip!member/path`.
+    pub into_archives: bool,
+    /// Order of entries within each directory.
+    pub sort: SortKey,
+    /// Reverse `sort`, so the largest, newest or last by name come first.
+    pub reverse: bool,
+    /// List each directory's contents right after its entry, depth
+    /// first, rather than after the whole of its parent's listing.
+    pub tree_order: bool,
+    pub mode_format: ModeFormat,
+    /// Escape paths reversibly (see `escape_bytes()`) instead of
+    /// replacing what isn't UTF-8.
+    pub escape_paths: bool,
+    /// Replace every name in the paths reported (and in link targets)
+    /// with its digest, cut to `hash_len`, to share a tree's shape
+    /// without its names.
+    pub hash_names: bool,
+    /// Leading components dropped from the paths reported, as with
+    /// `tar --strip-components`.  Entries no deeper than this report
+    /// an empty path.
+    pub strip_components: usize,
+    /// Show the raw uid and gid instead of looking up names.
+    pub numeric_ids: bool,
+    /// Read every user and group name once, as the scan starts, rather
+    /// than looking each up when first seen, so a change to the
+    /// databases mid-scan can't show one id two ways.  Ids missing from
+    /// them show as "?".
+    pub snapshot_ids: bool,
+    /// strftime pattern for timestamps.
+    pub time_format: String,
+    /// Show timestamps in the local zone instead of UTC.
+    pub local_time: bool,
+    /// Which timestamps to gather; mtime is always filled in.
+    pub times: Vec<TimeKind>,
+    /// Fill in each entry's inode number.
+    pub show_inodes: bool,
+    /// Read each entry's extended attributes.
+    pub xattrs: bool,
+    /// Note the file capabilities of regular files that have them.
+    pub caps: bool,
+    /// Note setuid, setgid and sticky bits.
+    pub special_bits: bool,
+}
+
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            debug: false,
+            max_sum_size: 3 * 1024*1024,
+            hash_len: 8,
+            hash: HashAlgo::Md5,
+            no_hash: false,
+            stat_only: false,
+            full_hash: false,
+            hash_empty: false,
+            head_hash: None,
+            jobs: 1,
+            threads: 1,
+            root_jobs: 1,
+            read_chunk: hash::DEFAULT_CHUNK,
+            mmap: false,
+            exclude: Vec::new(),
+            include: Vec::new(),
+            gitignore: false,
+            skip_hidden: false,
+            ignore_case: false,
+            ignore_file: true,
+            max_depth: None,
+            min_size: None,
+            max_size: None,
+            since: None,
+            until: None,
+            owner: None,
+            group: None,
+            mode_mask: None,
+            mode_match: None,
+            retries: 0,
+            limit_rate: None,
+            empty: false,
+            max_files: None,
+            cross_device: false,
+            resolve_mountpoints: false,
+            follow_symlinks: false,
+            follow_only: Vec::new(),
+            dedup_hardlinks: false,
+            count_symlinks: false,
+            deref_info: false,
+            into_archives: false,
+            blocks: false,
+            sort: SortKey::Name,
+            reverse: false,
+            tree_order: false,
+            mode_format: ModeFormat::Symbolic,
+            numeric_ids: false,
+            snapshot_ids: false,
+            escape_paths: false,
+            hash_names: false,
+            strip_components: 0,
+            time_format: DEFAULT_TIME_FORMAT.into(),
+            local_time: false,
+            times: vec![TimeKind::Modified],
+            show_inodes: false,
+            xattrs: false,
+            caps: false,
+            special_bits: false,
+        }
+    }
+}
+
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    Name,
+    /// Smallest first; directories count as empty
+    Size,
+    /// Oldest first
+    Mtime,
+    /// Whatever order the filesystem returns (not deterministic)
+    None,
+}
+
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModeFormat {
+    /// Like `ls -l`: -rwxr-xr-x
+    Symbolic,
+    /// Four digits including setuid/setgid/sticky: 0755
+    Octal,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeKind {
+    Modified,
+    Accessed,
+    /// Inode change time, not creation.
+    Changed,
+}
+
+
+impl TimeKind {
+    /// Parse one of the letters `m`, `a` or `c`.
+    pub fn parse(text: &str) -> Result<TimeKind, String> {
+        match text.trim() {
+            "m" => Ok(TimeKind::Modified),
+            "a" => Ok(TimeKind::Accessed),
+            "c" => Ok(TimeKind::Changed),
+            other => Err(format!("unknown time {:?}, expected m, a or c", other)),
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub enum Error {
+    /// A glob from the named option didn't compile.
+    Pattern(&'static str, globset::Error),
+    /// The two named options can't be used together.
+    Conflict(&'static str, &'static str),
+}
+
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Pattern(opt, e) => write!(f, "bad {} pattern: {}", opt, e),
+            Error::Conflict(a, b) => write!(f, "{} can't be used with {}", a, b),
+        }
+    }
+}
+
+
+impl std::error::Error for Error {}
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    File,
+    Dir,
+    Symlink,
+    Special,
+}
+
+
+impl Kind {
+    /// Lowercase name, as in the JSON output.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::File => "file",
+            Kind::Dir => "dir",
+            Kind::Symlink => "symlink",
+            Kind::Special => "special",
+        }
+    }
+}
+
+
+/// Everything gathered about one path.  `path` is relative to the scan
+/// root; `name` is just the final component.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScanEntry {
+    pub path: String,
+    #[serde(skip)]
+    pub name: String,
+    /// Where the entry is on disk: the root joined with `path`, or empty
+    /// for archive members.
+    #[serde(skip)]
+    pub fs_path: PathBuf,
+    pub size: u64,
+    /// Permissions as symbolic or octal text, or None if the path
+    /// couldn't be stat'd.
+    pub mode: Option<String>,
+    /// The raw `st_mode` behind `mode`.
+    #[serde(skip)]
+    pub mode_bits: Option<u32>,
+    pub owner: String,
+    pub group: String,
+    /// The numbers behind `owner` and `group`, where the platform has them.
+    #[serde(skip)]
+    pub uid: Option<u32>,
+    #[serde(skip)]
+    pub gid: Option<u32>,
+    /// With `ScanOptions::show_inodes`, where the platform has them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inode: Option<u64>,
+    /// Hard links to the entry, where the platform has them.
+    #[serde(skip)]
+    pub nlink: Option<u64>,
+    pub mtime: String,
+    /// `mtime` as seconds since the epoch.
+    #[serde(skip)]
+    pub mtime_unix: Option<i64>,
+    /// `mtime` to the nanosecond, for telling whether a digest from an
+    /// earlier scan still holds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime_ns: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub atime: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ctime: Option<String>,
+    /// Truncated hex digest, after a `~` if only the start of the file
+    /// was hashed.  A file that wasn't hashed has `empty` if it has no
+    /// contents, `skipped` if it was too big, `error` if it couldn't
+    /// be read (see `error`), or dashes if hashing was off.
+    pub hash: String,
+    /// What `hash` was made with, when it's a digest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_algo: Option<HashAlgo>,
+    pub target: Option<String>,
+    pub kind: Kind,
+    /// Extended attributes by name, when asked for.  Values are shown
+    /// as text if they're printable, otherwise as `0x` and hex.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub xattrs: BTreeMap<String, String>,
+    /// Annotations such as "mountpoint".
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+    /// Why the entry couldn't be stat'd or its contents read, when that
+    /// failed; it's also reported as `Event::Unreadable`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Difference from a baseline scan, when comparing against one;
+    /// the scanner itself leaves this empty.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change: Option<Change>,
+}
+
+
+impl ScanEntry {
+    /// Whether `hash` is a digest rather than a placeholder (or nothing,
+    /// for entries that aren't hashed).
+    pub fn has_digest(&self) -> bool {
+        !self.hash.is_empty() && self.hash.bytes().all(|b| b.is_ascii_hexdigit())
+    }
+}
+
+
+/// One step of a scan, in output order.
+#[derive(Debug)]
+pub enum Event {
+    /// A new root begins, as given to `add_root()`.
+    Root(PathBuf),
+    /// A subdirectory's listing begins; the path is relative to the root.
+    Dir(PathBuf),
+    /// In tree order, a listing carries on after a nested one; the path
+    /// is relative to the root, so empty for the root itself.
+    DirResume(PathBuf),
+    Entry(Box<ScanEntry>),
+    /// A directory entry that couldn't be read.
+    Error(walkdir::Error),
+    /// A path that was listed but then couldn't be stat'd or read; the
+    /// scan carries on without it.
+    Unreadable(PathBuf, io::Error),
+    /// A listing ended, with the bytes counted directly in it.
+    DirEnd(u64),
+    /// A root ended, with the bytes counted beneath it.
+    RootEnd(u64),
+}
+
+
+/// Totals over every entry reported, across all roots.
+#[derive(Clone, Debug, Default)]
+pub struct Summary {
+    pub files: u64,
+    pub dirs: u64,
+    pub symlinks: u64,
+    /// Devices, FIFOs, sockets, and anything that couldn't be stat'd.
+    pub others: u64,
+    /// The roots' byte totals added up.
+    pub bytes: u64,
+    /// Bytes of file contents read for the hash column.
+    pub hashed: u64,
+    /// Regular files by extension; those without one are under "".
+    pub extensions: BTreeMap<String, u64>,
+    /// Their sizes added up, likewise by extension.
+    pub extension_bytes: BTreeMap<String, u64>,
+    /// Path and size of the biggest regular file, first found on ties:
+    /// its path on disk, or as reported if it has none or names are hashed.
+    pub largest: Option<(PathBuf, u64)>,
+    /// Entries reported for being empty, with `ScanOptions::empty`.
+    pub empty: u64,
+}
+
+
+impl Summary {
+    fn add(&mut self, entry: &ScanEntry, hashed_names: bool) {
+        match entry.kind {
+            Kind::File => {
+                self.files += 1;
+                let ext = Path::new(&entry.name).extension()
+                    .map(|ext| ext.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                *self.extension_bytes.entry(ext.clone()).or_insert(0) += entry.size;
+                *self.extensions.entry(ext).or_insert(0) += 1;
+                if self.largest.as_ref().is_none_or(|(_, size)| entry.size > *size) {
+                    let on_disk = !entry.fs_path.as_os_str().is_empty() && !hashed_names;
+                    let path = if on_disk { entry.fs_path.clone() } else { PathBuf::from(&entry.path) };
+                    self.largest = Some((path, entry.size));
+                }
+            }
+            Kind::Dir => self.dirs += 1,
+            Kind::Symlink => self.symlinks += 1,
+            Kind::Special => self.others += 1,
+        }
+    }
+
+
+    /// Add in the totals of a root scanned separately, after the ones
+    /// already here.
+    fn merge(&mut self, other: Summary) {
+        self.files += other.files;
+        self.dirs += other.dirs;
+        self.symlinks += other.symlinks;
+        self.others += other.others;
+        self.bytes += other.bytes;
+        self.hashed += other.hashed;
+        self.empty += other.empty;
+        for (ext, count) in other.extensions {
+            *self.extensions.entry(ext).or_insert(0) += count;
+        }
+        for (ext, bytes) in other.extension_bytes {
+            *self.extension_bytes.entry(ext).or_insert(0) += bytes;
+        }
+        if let Some((path, size)) = other.largest {
+            if self.largest.as_ref().is_none_or(|(_, largest)| size > *largest) {
+                self.largest = Some((path, size));
+            }
+        }
+    }
+}
+
+
+/// Work still to do, most recent last.
+enum Pending {
+    Dir(u32, PathBuf),
+    /// An event held back to come after a subdirectory's, in tree order.
+    Emit(Event),
+    RootEnd,
+}
+
+
+pub struct Scanner {
+    opts: ScanOptions,
+    users: HashMap::<u32, String>,
+    groups: HashMap::<u32, String>,
+    /// Whether to look up ids missing from those, or take them as unknown.
+    lookup_ids: bool,
+    excludes: GlobSet,
+    includes: GlobSet,
+    /// Symlinked directories to follow, with `follow_only`.
+    follows: GlobSet,
+    /// Git's global excludes, when honouring `.gitignore`.
+    global_ignore: Option<Gitignore>,
+    /// Each directory's `.gitignore` this root, by directory.
+    gitignores: HashMap<PathBuf, Gitignore>,
+    /// This root's `.treescanignore`, if it has one.
+    root_ignore: Option<Gitignore>,
+    roots: VecDeque<PathBuf>,
+    stack: Vec<Pending>,
+    queue: VecDeque<Event>,
+    root: PathBuf,
+    /// The root's filesystem, or with `cross_device` the one being listed.
+    dev: u64,
+    count: u64,
+    /// (dev, inode) of directories entered this root, when following
+    /// symlinks, so cycles end.
+    visited: HashSet<(u64, u64)>,
+    /// (dev, inode) of files with more than one link seen this root.
+    links: HashSet<(u64, u64)>,
+    /// Error events handed out so far, across all roots.
+    errors: u64,
+    /// Entries reported so far, across all roots.
+    reported: u64,
+    /// Whether `max_files` cut the scan short.
+    truncated: bool,
+    summary: Summary,
+    /// The directory most recently listed.
+    current: PathBuf,
+    /// Listings being read ahead, with more than one thread.
+    prefetch: Option<Prefetch>,
+    /// What's been finished, this scan and any it resumes.
+    checkpoint: Checkpoint,
+    /// The top-level directory of this root being scanned.
+    top: Option<PathBuf>,
+    /// A baseline's digests, by path, to reuse for files that look the same.
+    known: Arc<HashMap<String, KnownDigest>>,
+    /// Pacing for content reads, with `limit_rate`, shared with the
+    /// scanners for other roots.
+    throttle: Option<Arc<Throttle>>,
+    /// With `resolve_mountpoints`, each filesystem's type and source by
+    /// device number.
+    mounts: HashMap<u64, String>,
+}
+
+
+impl Scanner {
+    pub fn new(opts: ScanOptions) -> Result<Self, Error> {
+        if opts.root_jobs > 1 && opts.max_files.is_some() {
+            return Err(Error::Conflict("--root-jobs", "--max-files"));
+        }
+        let excludes = build_globs(&opts.exclude, opts.ignore_case)
+            .map_err(|e| Error::Pattern("--exclude", e))?;
+        let includes = build_globs(&opts.include, opts.ignore_case)
+            .map_err(|e| Error::Pattern("--include", e))?;
+        let follows = build_globs(&opts.follow_only, opts.ignore_case)
+            .map_err(|e| Error::Pattern("--follow-only", e))?;
+        let global_ignore = if opts.gitignore {
+            Some(Gitignore::global().0)
+        }
+        else {
+            None
+        };
+        let lister = Lister { sort: opts.sort, reverse: opts.reverse, cross_device: opts.cross_device };
+        let prefetch = if opts.threads > 1 {
+            Some(Prefetch::new(lister, opts.threads))
+        }
+        else {
+            None
+        };
+
+        let snapshot = opts.snapshot_ids && !opts.numeric_ids;
+        let (users, groups) = if snapshot {
+            (platform::all_users().into_iter().collect(), platform::all_groups().into_iter().collect())
+        }
+        else {
+            (HashMap::new(), HashMap::new())
+        };
+        let throttle = opts.limit_rate.map(|rate| Arc::new(Throttle::new(rate)));
+        let mounts = if opts.resolve_mountpoints { platform::mounts() } else { HashMap::new() };
+
+        Ok(Self {
+            lookup_ids: !snapshot,
+            opts,
+            users,
+            groups,
+            excludes,
+            includes,
+            follows,
+            global_ignore,
+            gitignores: HashMap::new(),
+            root_ignore: None,
+            roots: VecDeque::new(),
+            stack: Vec::new(),
+            queue: VecDeque::new(),
+            root: PathBuf::new(),
+            dev: 0,
+            count: 0,
+            visited: HashSet::new(),
+            links: HashSet::new(),
+            errors: 0,
+            reported: 0,
+            truncated: false,
+            summary: Summary::default(),
+            current: PathBuf::new(),
+            prefetch,
+            checkpoint: Checkpoint::default(),
+            top: None,
+            known: Arc::default(),
+            throttle,
+            mounts,
+        })
+    }
+
+
+    /// Queue another root; roots are scanned in the order added.
+    pub fn add_root(&mut self, root: impl Into<PathBuf>) {
+        self.roots.push_back(root.into());
+    }
+
+
+    /// Carry on from an interrupted scan: skip the roots `checkpoint`
+    /// says were finished, and under the rest, the top-level
+    /// directories it says were.
+    pub fn resume(&mut self, checkpoint: Checkpoint) {
+        self.checkpoint = checkpoint;
+    }
+
+
+    /// Take the digests of files whose path, size and mtime are as
+    /// `known` has them from there rather than reading them again.  The
+    /// mtime is compared to the nanosecond, and the digests must have
+    /// been made with the same algorithm and length.  An edit that keeps
+    /// both the size and the mtime still goes unnoticed.
+    pub fn reuse_digests(&mut self, known: HashMap<String, KnownDigest>) {
+        self.known = Arc::new(known);
+    }
+
+
+    /// Progress so far, including any resumed, for saving.  A root or
+    /// top-level directory counts as finished once all its events have
+    /// been handed out.
+    pub fn checkpoint(&mut self) -> &mut Checkpoint {
+        &mut self.checkpoint
+    }
+
+
+    /// Mark the top-level directory in progress as finished.
+    fn finish_top(&mut self) {
+        if let Some(top) = self.top.take() {
+            let relative = top.strip_prefix(&self.root).unwrap_or(&top);
+            self.checkpoint.finish_dir(&self.root, relative);
+        }
+    }
+
+
+    /// How many entries so far couldn't be read or stat'd.
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+
+
+    /// Whether the scan stopped at `max_files` with more left to report.
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+
+    /// Totals so far; complete once the iterator is exhausted.
+    pub fn summary(&self) -> &Summary {
+        &self.summary
+    }
+
+
+    /// The directory being listed, or most recently listed, for progress
+    /// reports.
+    pub fn current_dir(&self) -> &Path {
+        &self.current
+    }
+
+
+    /// Path relative to the current root, as used for glob matching.
+    fn relative<'p>(&self, path: &'p Path) -> &'p Path {
+        path.strip_prefix(&self.root).unwrap_or(path)
+    }
+
+
+    /// `path` from the root, less `strip_components` leading names.
+    fn reported<'p>(&self, path: &'p Path) -> &'p Path {
+        let mut components = self.relative(path).components();
+        for _ in 0..self.opts.strip_components {
+            components.next();
+        }
+        components.as_path()
+    }
+
+
+    fn excluded(&self, path: &Path) -> bool {
+        self.excludes.is_match(self.relative(path))
+    }
+
+
+    /// Whether `skip_hidden` leaves out `path`, a dotfile.
+    fn hidden(&self, path: &Path) -> bool {
+        self.opts.skip_hidden && path.file_name().is_some_and(|name| name.as_encoded_bytes().starts_with(b"."))
+    }
+
+
+    /// Whether the root's `.treescanignore` skips `path`.
+    fn ignore_file_skips(&self, path: &Path, is_dir: bool) -> bool {
+        self.root_ignore.as_ref()
+            .is_some_and(|ignore| ignore.matched(path, is_dir).is_ignore())
+    }
+
+
+    /// Whether the `.gitignore` files between the root and `path`, or
+    /// the global one, ignore it; the closest match wins, as in Git.
+    fn ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let dirs = path.ancestors().skip(1).take_while(|dir| dir.starts_with(&self.root));
+        let ignores = dirs.filter_map(|dir| self.gitignores.get(dir)).chain(&self.global_ignore);
+        for gitignore in ignores {
+            match gitignore.matched(path, is_dir) {
+                ignore::Match::Ignore(_) => return true,
+                ignore::Match::Whitelist(_) => return false,
+                ignore::Match::None => {}
+            }
+        }
+        false
+    }
+
+
+    /// With no include patterns everything is included.
+    fn included(&self, path: &Path) -> bool {
+        self.includes.is_empty() || self.includes.is_match(self.relative(path))
+    }
+
+
+    /// Size filters apply to regular files only, not directories or links.
+    fn size_ok(&self, path: &Path) -> bool {
+        if self.opts.min_size.is_none() && self.opts.max_size.is_none() {
+            return true;
+        }
+        if path.is_symlink() || !path.is_file() {
+            return true;
+        }
+
+        let len = match path.metadata() {
+            Ok(meta) => meta.len(),
+            Err(_) => return true,
+        };
+        self.opts.min_size.is_none_or(|min| len >= min)
+            && self.opts.max_size.is_none_or(|max| len <= max)
+    }
+
+
+    /// Time filters likewise apply to regular files only.
+    fn time_ok(&self, path: &Path) -> bool {
+        if self.opts.since.is_none() && self.opts.until.is_none() {
+            return true;
+        }
+        if path.is_symlink() || !path.is_file() {
+            return true;
+        }
+
+        let mtime = match path.metadata().and_then(|meta| meta.modified()) {
+            Ok(mtime) => mtime,
+            Err(_) => return true,
+        };
+        self.opts.since.is_none_or(|since| mtime >= since)
+            && self.opts.until.is_none_or(|until| mtime <= until)
+    }
+
+
+    /// The metadata reported for `path`: a symlink's own, anything else's
+    /// followed, trying again after transient failures.
+    fn entry_meta(&self, path: &Path) -> io::Result<Metadata> {
+        retrying(self.opts.retries, || {
+            if path.is_symlink() {
+                std::fs::symlink_metadata(path)
+            }
+            else {
+                path.metadata()
+            }
+        })
+    }
+
+
+    /// List `dir`, trying again after transient failures to read it.
+    fn list(&mut self, dir: &Path) -> prefetch::Listing {
+        let lister = Lister { sort: self.opts.sort, reverse: self.opts.reverse, cross_device: self.opts.cross_device };
+        let mut listing = match &mut self.prefetch {
+            Some(prefetch) => prefetch.take(dir),
+            None => lister.list(dir),
+        };
+        for attempt in 0..self.opts.retries {
+            // only the directory itself failing, not one of its entries
+            let failed = match listing.first() {
+                Some(Err(e)) => e.depth() == 0 && e.io_error().is_some_and(transient),
+                _ => false,
+            };
+            if !failed {
+                break;
+            }
+            backoff(attempt);
+            listing = lister.list(dir);
+        }
+        listing
+    }
+
+
+    /// Ownership filters, matched against the id or its name.  Entries
+    /// whose owner can't be read, or that have none on this platform, pass.
+    fn owner_ok(&mut self, path: &Path) -> bool {
+        if self.opts.owner.is_none() && self.opts.group.is_none() {
+            return true;
+        }
+
+        let meta = self.entry_meta(path);
+        let (uid, gid) = match meta.ok().and_then(|meta| platform::file_owner(&meta)) {
+            Some(ids) => ids,
+            None => return true,
+        };
+        if let Some(owner) = self.opts.owner.clone() {
+            let ignore_case = self.opts.ignore_case;
+            if !id_matches(&owner, uid, self.user_name(uid), ignore_case) {
+                return false;
+            }
+        }
+        if let Some(group) = self.opts.group.clone() {
+            let ignore_case = self.opts.ignore_case;
+            if !id_matches(&group, gid, self.group_name(gid), ignore_case) {
+                return false;
+            }
+        }
+        true
+    }
+
+
+    /// The mode filter, on permission bits only.  Entries whose mode
+    /// can't be read pass.
+    fn mode_ok(&self, path: &Path) -> bool {
+        let mask = match self.opts.mode_mask {
+            Some(mask) => mask & 0o7777,
+            None => return true,
+        };
+        let want = self.opts.mode_match.map_or(mask, |want| want & 0o7777);
+
+        let meta = self.entry_meta(path);
+        match meta {
+            Ok(meta) => platform::file_mode(&meta) & mask == want,
+            Err(_) => true,
+        }
+    }
+
+
+    /// With `empty`, only zero-length files and directories with nothing
+    /// in them pass; so does nothing that can't be read.
+    fn empty_ok(&self, path: &Path) -> bool {
+        if !self.opts.empty {
+            return true;
+        }
+        if path.is_symlink() {
+            return false;
+        }
+
+        match path.metadata() {
+            Ok(meta) if meta.is_file() => meta.len() == 0,
+            Ok(meta) if meta.is_dir() => std::fs::read_dir(path).is_ok_and(|mut list| list.next().is_none()),
+            _ => false,
+        }
+    }
+
+
+    /// Whether entries `level` levels below the root (walkdir depth) should
+    /// be listed.  A directory at `depth` lists entries at level `depth + 1`.
+    fn within_depth(&self, level: u32) -> bool {
+        match self.opts.max_depth {
+            Some(max) => level <= max,
+            None => true,
+        }
+    }
+
+
+    fn start_root(&mut self, root: PathBuf) {
+        if self.opts.debug {
+            eprintln!("{:?}", root.metadata());
+        }
+        if self.checkpoint.root_done(&root) {
+            return;
+        }
+
+        let meta = match root.metadata() {
+            Ok(meta) => meta,
+            Err(e) => {
+                self.queue.push_back(Event::Unreadable(root, e));
+                return;
+            }
+        };
+        self.dev = platform::file_device(&root, &meta);
+        self.count = 0;
+        self.visited.clear();
+        self.links.clear();
+        self.gitignores.clear();
+        self.root_ignore = None;
+        if self.opts.ignore_file {
+            self.root_ignore = self.load_ignore(&root.join(IGNORE_FILE));
+        }
+        if self.following() {
+            self.visited.insert(platform::file_id(&root, &meta));
+        }
+        self.root = root.clone();
+        self.top = None;
+
+        self.queue.push_back(Event::Root(root.clone()));
+        self.stack.push(Pending::RootEnd);
+        self.stack.push(Pending::Dir(0, root));
+    }
+
+
+    /// Scan the next `root_jobs` roots at once, each with a scanner of
+    /// its own, and queue their events in order.
+    fn scan_roots(&mut self) {
+        let count = self.opts.root_jobs.min(self.roots.len());
+        let roots: Vec<PathBuf> = self.roots.drain(..count).collect();
+        // the names found so far, or the snapshot, go to every worker
+        // rather than each reading its own
+        let opts = ScanOptions { root_jobs: 1, snapshot_ids: false, ..self.opts.clone() };
+        let ids = (&self.users, &self.groups, self.lookup_ids);
+
+        let scanned: Vec<(Vec<Event>, Summary)> = std::thread::scope(|s| {
+            let workers: Vec<_> = roots.into_iter().map(|root| {
+                let opts = opts.clone();
+                let known = Arc::clone(&self.known);
+                let throttle = self.throttle.clone();
+                s.spawn(move || {
+                    // the same options already made this scanner
+                    let mut scanner = Scanner::new(opts).expect("options checked in new()");
+                    scanner.known = known;
+                    scanner.throttle = throttle;
+                    (scanner.users, scanner.groups, scanner.lookup_ids) = (ids.0.clone(), ids.1.clone(), ids.2);
+                    scanner.add_root(root);
+                    let events = scanner.by_ref().collect();
+                    (events, scanner.summary)
+                })
+            }).collect();
+            workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+        });
+
+        for (events, summary) in scanned {
+            self.queue.extend(events);
+            self.summary.merge(summary);
+        }
+    }
+
+
+    /// Queue the events for one directory's listing, and stack its
+    /// subdirectories so they're visited next, in order.
+    fn visit(&mut self, depth: u32, dir: PathBuf) {
+        if depth > 0 {
+            if self.opts.debug {
+                eprintln!("{:?}", dir.metadata());
+            }
+            self.queue.push_back(Event::Dir(self.header_path(&dir)));
+        }
+
+        if !self.within_depth(depth + 1) {
+            return;
+        }
+
+        if self.opts.gitignore {
+            self.load_gitignore(&dir);
+        }
+        self.current.clone_from(&dir);
+
+        // mountpoints are marked relative to the filesystem being listed
+        if self.opts.cross_device {
+            if let Ok(meta) = dir.metadata() {
+                self.dev = platform::file_device(&dir, &meta);
+            }
+        }
+
+        let listing = self.list(&dir);
+
+        // subdirectories, by the place in the queue just after their entry
+        let mut dirs: Vec<(usize, PathBuf)> = Vec::new();
+        // files to hash, by their place in the queue
+        let mut work: Vec<(usize, PathBuf, u64)> = Vec::new();
+        let start = self.count;
+        let first = self.queue.len();
+
+        for res in listing {
+            if self.opts.debug {
+                eprintln!("visit {:?}", res);
+            }
+
+            match res {
+                Ok(entry) => {
+                    let path = entry.path();
+
+                    let is_dir = entry.file_type().is_dir();
+                    if self.excluded(path) || self.hidden(path) || self.ignore_file_skips(path, is_dir)
+                        || self.ignored(path, is_dir) {
+                        if self.opts.debug {
+                            eprintln!("excluded {:?}", path);
+                        }
+                        continue;
+                    }
+
+                    if self.included(path) && self.size_ok(path) && self.time_ok(path) && self.empty_ok(path)
+                        && self.owner_ok(path) && self.mode_ok(path) {
+                        if self.opts.max_files.is_some_and(|max| self.reported >= max) {
+                            self.truncated = true;
+                            break;
+                        }
+                        self.reported += 1;
+                        if self.opts.empty {
+                            self.summary.empty += 1;
+                        }
+                        let (mut entry, mut hash) = self.report(path);
+                        if let Some(digest) = hash.and_then(|_| self.known_digest(&entry)) {
+                            if self.opts.debug {
+                                eprintln!("reused {:?}", path);
+                            }
+                            entry.hash = digest;
+                            entry.hash_algo = Some(self.opts.hash);
+                            hash = None;
+                        }
+                        if let Some(len) = hash {
+                            if self.opts.debug {
+                                eprintln!("hashing {:?}", path);
+                            }
+                            work.push((self.queue.len(), path.to_path_buf(), len));
+                        }
+                        self.summary.add(&entry, self.opts.hash_names);
+                        let format = archive::Format::of(path)
+                            .filter(|_| self.opts.into_archives && !self.opts.stat_only && entry.kind == Kind::File);
+                        let parent = format.map(|_| (entry.path.clone(), entry.name.clone()));
+                        self.queue.push_back(Event::Entry(Box::new(entry)));
+                        if let (Some(format), Some(parent)) = (format, parent) {
+                            self.expand(path, format, parent);
+                        }
+                    }
+
+                    if self.descend(depth, path) {
+                        dirs.push((self.queue.len(), path.to_path_buf()));
+                    }
+                }
+                Err(e) => self.queue.push_back(Event::Error(e)),
+            }
+        }
+
+        // finish what's been listed, held back or not, but go no further
+        if self.truncated {
+            dirs.clear();
+            // along with any of their listings being read ahead
+            let prefetch = &mut self.prefetch;
+            self.stack.retain(|pending| match pending {
+                Pending::Dir(_, dir) => {
+                    if let Some(prefetch) = prefetch {
+                        prefetch.cancel(dir);
+                    }
+                    false
+                }
+                _ => true,
+            });
+            self.roots.clear();
+        }
+
+        let paths: Vec<PathBuf> = work.iter().map(|(_, path, _)| path.clone()).collect();
+        let head = self.opts.head_hash;
+        let throttle = self.throttle.as_deref();
+        let digests = hash::hash_files(&paths, self.opts.hash, self.opts.jobs, self.opts.read_chunk, head,
+                                       self.opts.mmap, throttle);
+        for ((index, path, len), hashed) in work.into_iter().zip(digests) {
+            self.settle_hash(index, path, len, hashed);
+        }
+
+        let end = Event::DirEnd(self.count - start);
+
+        if !self.opts.tree_order {
+            self.queue.push_back(end);
+            for (_, sub) in dirs.into_iter().rev() {
+                self.stack.push(Pending::Dir(depth + 1, sub));
+            }
+            return;
+        }
+
+        // hold the listing back so each subdirectory's comes right
+        // after its own entry
+        let mut events = self.queue.split_off(first).into_iter();
+        let mut order = Vec::new();
+        let mut at = first;
+        let resume = self.header_path(&dir);
+        for (after, sub) in dirs {
+            order.extend(events.by_ref().take(after - at).map(Pending::Emit));
+            at = after;
+            order.push(Pending::Dir(depth + 1, sub));
+            order.push(Pending::Emit(Event::DirResume(resume.clone())));
+        }
+        order.extend(events.map(Pending::Emit));
+        order.push(Pending::Emit(end));
+        self.stack.extend(order.into_iter().rev());
+    }
+
+
+    /// Queue entries for the members of the archive at `path`, whose
+    /// entry has the given path and name.  They're named after it, and
+    /// count towards neither the totals nor the summary.
+    fn expand(&mut self, path: &Path, format: archive::Format, (parent_path, parent_name): (String, String)) {
+        let members = archive::members(path, format, self.opts.hash, self.opts.read_chunk, self.opts.head_hash,
+                                       self.throttle.as_deref(), |size| self.wants_hash(size));
+        let members = match members {
+            Ok(members) => members,
+            Err(e) => {
+                self.queue.push_back(Event::Unreadable(path.to_path_buf(), e));
+                return;
+            }
+        };
+
+        for member in members {
+            let inner = if self.opts.hash_names {
+                self.path_text(Path::new(&member.path))
+            }
+            else if self.opts.escape_paths {
+                escape_bytes(member.path.as_bytes())
+            }
+            else {
+                member.path.clone()
+            };
+            let mut entry = ScanEntry {
+                path: format!("{}!{}", parent_path, inner),
+                name: format!("{}!{}", parent_name, inner),
+                fs_path: PathBuf::new(),
+                size: member.size,
+                mode: member.mode.map(|bits| match self.opts.mode_format {
+                    ModeFormat::Symbolic => unix_mode::to_string(bits),
+                    ModeFormat::Octal => format!("{:04o}", bits & 0o7777),
+                }),
+                mode_bits: member.mode,
+                owner: match (&member.owner, member.uid) {
+                    (Some(name), _) if !self.opts.numeric_ids => name.clone(),
+                    (_, Some(uid)) => uid.to_string(),
+                    _ => String::new(),
+                },
+                group: match (&member.group, member.gid) {
+                    (Some(name), _) if !self.opts.numeric_ids => name.clone(),
+                    (_, Some(gid)) => gid.to_string(),
+                    _ => String::new(),
+                },
+                uid: member.uid,
+                gid: member.gid,
+                inode: None,
+                nlink: None,
+                mtime: match member.kind {
+                    Kind::Dir => String::new(),
+                    _ => self.format_time(member.mtime),
+                },
+                mtime_unix: member.mtime.map(|time| DateTime::<Utc>::from(time).timestamp()),
+                mtime_ns: member.mtime.and_then(unix_nanos),
+                // archives don't keep these
+                atime: self.opts.times.contains(&TimeKind::Accessed).then(|| "?".into()),
+                ctime: self.opts.times.contains(&TimeKind::Changed).then(|| "?".into()),
+                hash: match (&member.digest, member.kind) {
+                    (Some(digest), _) => {
+                        let digest = &digest[..self.opts.hash_len.min(digest.len())];
+                        if self.opts.head_hash.is_some() { format!("~{}", digest) } else { digest.into() }
+                    }
+                    (None, Kind::File) => self.placeholder(member.size),
+                    _ => String::new(),
+                },
+                hash_algo: None,
+                target: member.target,
+                kind: member.kind,
+                xattrs: BTreeMap::new(),
+                notes: Vec::new(),
+                error: None,
+                change: None,
+            };
+            entry.hash_algo = Some(self.opts.hash).filter(|_| entry.has_digest());
+            self.queue.push_back(Event::Entry(Box::new(entry)));
+        }
+    }
+
+
+    /// Have the next few directories due to be listed read ahead.
+    fn prefetch_ahead(&mut self) {
+        let prefetch = match &mut self.prefetch {
+            Some(prefetch) => prefetch,
+            None => return,
+        };
+        // enough to keep every thread busy, without reading far ahead
+        let want = self.opts.threads * 4;
+        for pending in self.stack.iter().rev().take(want * 16) {
+            if prefetch.outstanding() >= want {
+                break;
+            }
+            if let Pending::Dir(depth, dir) = pending {
+                // as within_depth(depth + 1), which visit() checks first
+                if self.opts.max_depth.is_none_or(|max| *depth < max) {
+                    prefetch.request(dir);
+                }
+            }
+        }
+    }
+
+
+    /// Pick up `dir/.gitignore`, if there is one, for the entries below.
+    fn load_gitignore(&mut self, dir: &Path) {
+        if let Some(gitignore) = self.load_ignore(&dir.join(".gitignore")) {
+            self.gitignores.insert(dir.to_path_buf(), gitignore);
+        }
+    }
+
+
+    /// Read a file of gitignore rules, if it's there and has any.
+    fn load_ignore(&self, file: &Path) -> Option<Gitignore> {
+        if !file.is_file() {
+            return None;
+        }
+
+        let mut builder = GitignoreBuilder::new(file.parent().unwrap_or(Path::new("/")));
+        // before adding anything, as it only affects what comes after
+        let _ = builder.case_insensitive(self.opts.ignore_case);
+        let mut err = builder.add(file);
+        let ignore = builder.build().unwrap_or_else(|e| {
+            err = Some(e);
+            Gitignore::empty()
+        });
+        if self.opts.debug {
+            if let Some(err) = err {
+                eprintln!("{:?}: {}", file, err);
+            }
+        }
+        (!ignore.is_empty()).then_some(ignore)
+    }
+
+
+    /// Whether a directory listed at `depth` should have its own listing.
+    fn descend(&mut self, depth: u32, path: &Path) -> bool {
+        if !path.is_dir() || !self.within_depth(depth + 2) {
+            return false;
+        }
+        // finished in the scan being resumed
+        if depth == 0 && self.checkpoint.dir_done(&self.root, self.relative(path)) {
+            return false;
+        }
+        if path.is_symlink() && !self.follows(path) {
+            return false;
+        }
+
+        // it may have gone away since it was listed
+        let meta = match retrying(self.opts.retries, || path.metadata()) {
+            Ok(meta) => meta,
+            Err(e) => {
+                self.queue.push_back(Event::Unreadable(path.to_path_buf(), e));
+                return false;
+            }
+        };
+        if !self.opts.cross_device && platform::file_device(path, &meta) != self.dev {
+            return false;
+        }
+
+        if self.following() && !self.visited.insert(platform::file_id(path, &meta)) {
+            if self.opts.debug {
+                eprintln!("already visited {:?}", path);
+            }
+            return false;
+        }
+
+        true
+    }
+
+
+    /// Whether any symlinks are followed, so cycles must be watched for.
+    fn following(&self) -> bool {
+        self.opts.follow_symlinks || !self.follows.is_empty()
+    }
+
+
+    /// Whether to descend into the symlinked directory at `path`.
+    fn follows(&self, path: &Path) -> bool {
+        if self.opts.follow_symlinks {
+            return true;
+        }
+        if self.follows.is_empty() {
+            return false;
+        }
+        self.follows.is_match(self.relative(path))
+            || std::fs::read_link(path).is_ok_and(|target| self.follows.is_match(target))
+    }
+
+
+    /// The digest `entry` had in the baseline, if it looks unchanged
+    /// since and the digest is the kind this scan would make.
+    fn known_digest(&self, entry: &ScanEntry) -> Option<String> {
+        let known = self.known.get(&entry.path)?;
+        let head = self.opts.head_hash.is_some();
+        let len = self.opts.hash_len.min(self.opts.hash.digest_len()) + head as usize;
+        let same = known.size == entry.size
+            && Some(known.mtime_ns) == entry.mtime_ns
+            && known.algo == self.opts.hash
+            && known.hash.len() == len
+            && known.hash.starts_with('~') == head;
+        same.then(|| known.hash.clone())
+    }
+
+
+    /// Whether a file of `len` bytes gets hashed, rather than showing a
+    /// placeholder: not if it's empty, too big, or hashing is off.
+    fn wants_hash(&self, len: u64) -> bool {
+        !self.opts.no_hash && !self.opts.stat_only && self.hashable(len)
+    }
+
+
+    /// Whether a file of `len` bytes is the kind that gets hashed, if
+    /// hashing is on.
+    fn hashable(&self, len: u64) -> bool {
+        let small = len < self.opts.max_sum_size || self.opts.full_hash || self.opts.head_hash.is_some();
+        len > 0 && small
+    }
+
+
+    /// With `stat_only` and `debug`, mention a file that would otherwise
+    /// have been hashed.
+    fn trace_unopened(&self, path: &Path, len: u64) {
+        if self.opts.stat_only && self.opts.debug && !self.opts.no_hash && self.hashable(len) {
+            eprintln!("would hash {:?}", path);
+        }
+    }
+
+
+    /// Fill in the hash of the entry at `index` in the queue, a file of
+    /// `len` bytes when stat'd, from a first go at hashing it, trying
+    /// again as `retries` allows after transient errors and short reads.
+    /// A file that still can't be read is reported unreadable, with the
+    /// last error.
+    fn settle_hash(&mut self, index: usize, path: PathBuf, len: u64, mut hashed: io::Result<(String, u64)>) {
+        let head = self.opts.head_hash;
+        let want = head.map_or(len, |head| len.min(head));
+        for attempt in 0..self.opts.retries {
+            let settled = match &hashed {
+                Ok((_, read)) => *read == want,
+                Err(e) => !transient(e),
+            };
+            if settled {
+                break;
+            }
+            backoff(attempt);
+            hashed = hash::hash_file(&path, self.opts.hash, self.opts.read_chunk, head, self.opts.mmap,
+                                      self.throttle.as_deref());
+        }
+
+        let (mut digest, read) = match hashed {
+            Ok(hashed) => hashed,
+            // still listed, but with no digest to show
+            Err(e) => {
+                if let Some(Event::Entry(entry)) = self.queue.get_mut(index) {
+                    entry.hash = "error".into();
+                    entry.error = Some(e.to_string());
+                }
+                self.queue.push_back(Event::Unreadable(path, e));
+                return;
+            }
+        };
+        digest.truncate(self.opts.hash_len);
+        if head.is_some() {
+            digest.insert(0, '~');
+        }
+        if let Some(Event::Entry(entry)) = self.queue.get_mut(index) {
+            entry.hash = digest;
+            entry.hash_algo = Some(self.opts.hash);
+            self.summary.hashed += read;
+            // written to (or truncated) since it was stat'ed
+            if read != want {
+                entry.notes.push("changed during scan".into());
+            }
+        }
+    }
+
+
+    /// Gather everything but the content hash.  If visit() should fill
+    /// that in, this also gives the length it's expected to hash;
+    /// otherwise the hash is already set.
+    fn report(&mut self, path: &Path) -> (ScanEntry, Option<u64>) {
+        let mut entry = ScanEntry {
+            path: self.path_text(self.reported(path)),
+            name: match path.file_name() {
+                Some(name) => self.path_text(Path::new(name)),
+                None => "?".into(),
+            },
+            fs_path: path.to_path_buf(),
+            size: 0,
+            mode: None,
+            mode_bits: None,
+            owner: String::new(),
+            group: String::new(),
+            uid: None,
+            gid: None,
+            inode: None,
+            nlink: None,
+            mtime: String::new(),
+            mtime_unix: None,
+            mtime_ns: None,
+            atime: None,
+            ctime: None,
+            hash: String::new(),
+            hash_algo: None,
+            target: None,
+            kind: Kind::Special,
+            xattrs: BTreeMap::new(),
+            notes: Vec::new(),
+            error: None,
+            change: None,
+        };
+
+        let meta = self.entry_meta(path);
+        let meta = match meta {
+            Ok(meta) => Some(meta),
+            // still listed, but with nothing to show beyond its name and why
+            Err(e) => {
+                entry.error = Some(e.to_string());
+                self.queue.push_back(Event::Unreadable(path.to_path_buf(), e));
+                None
+            }
+        };
+
+        // the filesystem, if it's not the root's
+        let otherdev;
+        let mut hardlink = false;
+        let mut hash = false;
+        // hashing goes by the real length even when showing blocks
+        let mut apparent = 0;
+        if let Some(meta) = meta {
+            entry.size = self.size_of(&meta);
+            apparent = meta.len();
+            let bits = platform::file_mode(&meta);
+            entry.mode_bits = Some(bits);
+            if self.opts.show_inodes {
+                entry.inode = platform::inode(&meta);
+            }
+            entry.nlink = Some(platform::link_count(&meta));
+            entry.mode = Some(match self.opts.mode_format {
+                ModeFormat::Symbolic => unix_mode::to_string(bits),
+                ModeFormat::Octal => format!("{:04o}", bits & 0o7777),
+            });
+            otherdev = Some(platform::file_device(path, &meta)).filter(|dev| *dev != self.dev);
+            entry.mtime = self.format_time(meta.modified().ok());
+            entry.mtime_unix = meta.modified().ok().map(|time| DateTime::<Utc>::from(time).timestamp());
+            entry.mtime_ns = meta.modified().ok().and_then(unix_nanos);
+            if self.opts.times.contains(&TimeKind::Accessed) {
+                entry.atime = Some(self.format_time(meta.accessed().ok()));
+            }
+            if self.opts.times.contains(&TimeKind::Changed) {
+                entry.ctime = Some(self.format_time(platform::change_time(&meta)));
+            }
+
+            if let Some((uid, gid)) = platform::file_owner(&meta) {
+                entry.uid = Some(uid);
+                entry.gid = Some(gid);
+                if self.opts.numeric_ids {
+                    entry.owner = uid.to_string();
+                    entry.group = gid.to_string();
+                }
+                else {
+                    entry.owner.push_str(self.user_name(uid));
+                    entry.group.push_str(self.group_name(gid));
+                }
+            }
+
+            if meta.is_file() && platform::link_count(&meta) > 1 {
+                hardlink = !self.links.insert(platform::file_id(path, &meta));
+            }
+        }
+        else {
+            otherdev = None;
+        }
+
+        if path.is_symlink() {
+            entry.kind = Kind::Symlink;
+            match std::fs::read_link(path) {
+                Ok(target) => entry.target = Some(self.path_text(&target)),
+                Err(e) => self.queue.push_back(Event::Unreadable(path.to_path_buf(), e)),
+            }
+            if self.opts.follow_symlinks && path.is_file() {
+                if let Ok(meta) = path.metadata() {
+                    entry.size = self.size_of(&meta);
+                    apparent = meta.len();
+                }
+                hash = self.wants_hash(apparent);
+                self.trace_unopened(path, apparent);
+                self.count += entry.size;
+            }
+            else if self.opts.count_symlinks {
+                self.count += entry.size;
+            }
+            if self.opts.deref_info {
+                entry.notes.push(match path.metadata() {
+                    Ok(meta) => deref_note(&meta),
+                    Err(_) => "dangling".into(),
+                });
+            }
+        }
+        else if path.is_dir() {
+            entry.kind = Kind::Dir;
+            entry.mtime.clear();
+            entry.mtime_unix = None;
+            entry.mtime_ns = None;
+            entry.atime = entry.atime.as_ref().map(|_| String::new());
+            entry.ctime = entry.ctime.as_ref().map(|_| String::new());
+            entry.size = 0;
+            if let Some(dev) = otherdev {
+                entry.notes.push(match self.mounts.get(&dev) {
+                    Some(mount) => format!("mountpoint: {}", mount),
+                    None => "mountpoint".into(),
+                });
+            }
+        }
+        else if path.is_file() {
+            entry.kind = Kind::File;
+            if hardlink {
+                entry.notes.push("hardlink".into());
+            }
+            if !(hardlink && self.opts.dedup_hardlinks) {
+                self.count += entry.size;
+            }
+            hash = self.wants_hash(apparent);
+            self.trace_unopened(path, apparent);
+        }
+        else if let Some(what) = entry.mode_bits.and_then(special_kind) {
+            entry.notes.push(what.into());
+        }
+
+        if entry.kind == Kind::File && !hash {
+            entry.hash = self.placeholder(apparent);
+            entry.hash_algo = Some(self.opts.hash).filter(|_| entry.has_digest());
+        }
+
+        if self.opts.caps && entry.kind == Kind::File {
+            // anything unreadable or undecodable just goes unmentioned
+            if let Ok(Some(value)) = platform::xattr(path, "security.capability") {
+                match caps::decode(&value) {
+                    Some(caps) => entry.notes.push(format!("caps: {}", caps)),
+                    None if self.opts.debug => eprintln!("bad capabilities on {:?}", path),
+                    None => {}
+                }
+            }
+        }
+
+        if self.opts.special_bits {
+            if let Some(bits) = entry.mode_bits {
+                entry.notes.extend(special_bits(bits).map(String::from));
+            }
+        }
+
+        // filesystems without xattr support just have none to show
+        if self.opts.xattrs {
+            if let Ok(attrs) = platform::xattrs(path) {
+                entry.xattrs = attrs.into_iter()
+                    .map(|(name, value)| (name, xattr_text(&value)))
+                    .collect();
+            }
+        }
+
+        (entry, hash.then_some(apparent))
+    }
+
+
+    /// What shows instead of the digest of a file of `len` bytes that
+    /// isn't hashed, saying why.
+    fn placeholder(&self, len: u64) -> String {
+        if self.opts.no_hash || self.opts.stat_only {
+            "-".repeat(self.opts.hash_len)
+        }
+        else if len == 0 && self.opts.hash_empty {
+            let mut digest = hash::Hasher::new(self.opts.hash).finalize();
+            digest.truncate(self.opts.hash_len);
+            if self.opts.head_hash.is_some() {
+                digest.insert(0, '~');
+            }
+            digest
+        }
+        else if len == 0 {
+            "empty".into()
+        }
+        else {
+            "skipped".into()
+        }
+    }
+
+
+    /// A path as text: lossy by default, or reversibly escaped, or
+    /// with its names hashed.
+    fn path_text(&self, path: &Path) -> String {
+        if self.opts.hash_names {
+            self.hashed_path(path).to_string_lossy().into_owned()
+        }
+        else if self.opts.escape_paths {
+            escape_bytes(&platform::path_bytes(path))
+        }
+        else {
+            path.to_string_lossy().into_owned()
+        }
+    }
+
+
+    /// `path` with each name replaced by its digest, keeping the
+    /// separators, a leading `/`, and any `.` or `..`.
+    fn hashed_path(&self, path: &Path) -> PathBuf {
+        path.components().map(|component| match component {
+            Component::Normal(name) => {
+                let mut hasher = hash::Hasher::new(self.opts.hash);
+                hasher.consume(&platform::path_bytes(Path::new(name)));
+                let mut digest = hasher.finalize();
+                digest.truncate(self.opts.hash_len);
+                digest.into()
+            }
+            other => other.as_os_str().to_os_string(),
+        }).collect()
+    }
+
+
+    /// A subdirectory's path relative to the root, for its header.
+    fn header_path(&self, dir: &Path) -> PathBuf {
+        let relative = self.reported(dir);
+        if self.opts.hash_names {
+            self.hashed_path(relative)
+        }
+        else {
+            relative.to_path_buf()
+        }
+    }
+
+
+    /// The size to show and count: apparent, or allocated with `blocks`.
+    fn size_of(&self, meta: &Metadata) -> u64 {
+        if self.opts.blocks {
+            platform::allocated_size(meta).unwrap_or(meta.len())
+        }
+        else {
+            meta.len()
+        }
+    }
+
+
+    /// Render a timestamp, or "?" if the platform didn't supply one.
+    fn format_time(&self, time: Option<SystemTime>) -> String {
+        let time = match time {
+            Some(time) => time,
+            None => return "?".into(),
+        };
+
+        if self.opts.local_time {
+            DateTime::<Local>::from(time).format(&self.opts.time_format).to_string()
+        }
+        else {
+            DateTime::<Utc>::from(time).format(&self.opts.time_format).to_string()
+        }
+    }
+
+
+    /// Name for `uid`, looked up once per scanner, or "?" if unknown.
+    fn user_name(&mut self, uid: u32) -> &str {
+        let lookup = self.lookup_ids;
+        self.users.entry(uid)
+            .or_insert_with(|| lookup.then(|| platform::user_name(uid)).flatten().unwrap_or_else(|| "?".into()))
+    }
+
+
+    fn group_name(&mut self, gid: u32) -> &str {
+        let lookup = self.lookup_ids;
+        self.groups.entry(gid)
+            .or_insert_with(|| lookup.then(|| platform::group_name(gid)).flatten().unwrap_or_else(|| "?".into()))
+    }
+}
+
+
+impl Iterator for Scanner {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        loop {
+            if let Some(event) = self.queue.pop_front() {
+                if let Event::Error(_) | Event::Unreadable(..) = event {
+                    self.errors += 1;
+                }
+                return Some(event);
+            }
+
+            match self.stack.pop() {
+                Some(Pending::Dir(depth, dir)) => {
+                    // everything under the previous one's been handed out
+                    if depth == 1 {
+                        self.finish_top();
+                        self.top = Some(dir.clone());
+                    }
+                    self.visit(depth, dir);
+                    self.prefetch_ahead();
+                }
+                Some(Pending::Emit(event)) => self.queue.push_back(event),
+                Some(Pending::RootEnd) => {
+                    // a scan cut short leaves the rest unfinished
+                    if !self.truncated {
+                        self.finish_top();
+                        self.checkpoint.finish_root(&self.root);
+                    }
+                    self.summary.bytes += self.count;
+                    self.queue.push_back(Event::RootEnd(self.count));
+                }
+                None if self.opts.root_jobs > 1 && self.roots.len() > 1 => self.scan_roots(),
+                None => {
+                    let root = self.roots.pop_front()?;
+                    self.start_root(root);
+                }
+            }
+        }
+    }
+}
+
+
+/// Scan a single root, yielding just the entries.
+pub fn scan_path(root: &Path, opts: &ScanOptions) -> Result<impl Iterator<Item = ScanEntry>, Error> {
+    let mut scanner = Scanner::new(opts.clone())?;
+    scanner.add_root(root);
+    Ok(scanner.filter_map(|event| match event {
+        Event::Entry(entry) => Some(*entry),
+        _ => None,
+    }))
+}
+
+
+/// Text for a byte string that can be turned back into the same bytes:
+/// backslashes are doubled, and control characters and bytes that
+/// aren't UTF-8 become `\xHH`.
+pub fn escape_bytes(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => text.push_str("\\\\"),
+                c if c.is_ascii_control() => text.push_str(&format!("\\x{:02x}", c as u8)),
+                c => text.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            text.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+    text
+}
+
+
+/// An xattr value as text if it all prints (allowing the trailing NUL
+/// many carry, as SELinux labels do), otherwise as hex.
+fn xattr_text(value: &[u8]) -> String {
+    let trimmed = value.strip_suffix(b"\0").unwrap_or(value);
+    match std::str::from_utf8(trimmed) {
+        Ok(text) if !text.chars().any(char::is_control) => text.into(),
+        _ => format!("0x{}", hex::encode(value)),
+    }
+}
+
+
+/// Describe a device, FIFO or socket from its `st_mode` type bits.
+fn special_kind(mode: u32) -> Option<&'static str> {
+    match mode & 0o170000 {
+        0o010000 => Some("fifo"),
+        0o020000 => Some("char device"),
+        0o060000 => Some("block device"),
+        0o140000 => Some("socket"),
+        _ => None,
+    }
+}
+
+
+/// Name the setuid, setgid and sticky bits set in `mode`.
+fn special_bits(mode: u32) -> impl Iterator<Item = &'static str> {
+    [(0o4000, "setuid"), (0o2000, "setgid"), (0o1000, "sticky")].into_iter()
+        .filter(move |(bit, _)| mode & bit != 0)
+        .map(|(_, name)| name)
+}
+
+
+/// What a symlink resolves to, e.g. `-> file, 4096 bytes`.
+fn deref_note(meta: &Metadata) -> String {
+    if meta.is_file() {
+        format!("-> file, {} bytes", meta.len())
+    }
+    else if meta.is_dir() {
+        "-> dir".into()
+    }
+    else {
+        format!("-> {}", special_kind(platform::file_mode(meta)).unwrap_or("special"))
+    }
+}
+
+
+/// `time` as nanoseconds since the epoch, if it's within the 584
+/// years an i64 spans.
+fn unix_nanos(time: SystemTime) -> Option<i64> {
+    DateTime::<Utc>::from(time).timestamp_nanos_opt()
+}
+
+
+/// Whether an error might not happen again: not a path that's missing
+/// or forbidden, say, but a timeout or I/O error from a network mount.
+fn transient(e: &io::Error) -> bool {
+    !matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied)
+}
+
+
+/// Wait before retry number `attempt`, from 0, doubling up to a second or so.
+fn backoff(attempt: u32) {
+    std::thread::sleep(RETRY_DELAY * 2u32.pow(attempt.min(4)));
+}
+
+
+/// Run `op`, up to `retries` more times while it fails transiently.
+fn retrying<T>(retries: u32, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(e) if attempt < retries && transient(&e) => {
+                backoff(attempt);
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+
+/// Whether `want`, a name or a numeric id, names this owner or group.
+fn id_matches(want: &str, id: u32, name: &str, ignore_case: bool) -> bool {
+    match want.parse::<u32>() {
+        Ok(want) => want == id,
+        Err(_) if ignore_case => want.to_lowercase() == name.to_lowercase(),
+        Err(_) => want == name,
+    }
+}
+
+
+fn build_globs(patterns: &[String], ignore_case: bool) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pat in patterns {
+        builder.add(GlobBuilder::new(pat).case_insensitive(ignore_case).build()?);
+    }
+    builder.build()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn scanner(opts: ScanOptions) -> Scanner {
+        Scanner::new(opts).unwrap()
+    }
+
+    #[test]
+    fn exclude_relative_to_root() {
+        let mut scanner = scanner(ScanOptions {
+            exclude: vec!["**/node_modules".into(), "*.o".into()],
+            ..Default::default()
+        });
+        scanner.root = PathBuf::from("/src");
+        assert!(scanner.excluded(Path::new("/src/node_modules")));
+        assert!(scanner.excluded(Path::new("/src/web/node_modules")));
+        assert!(scanner.excluded(Path::new("/src/lib/x.o")));
+        assert!(!scanner.excluded(Path::new("/src/node_modules.txt")));
+        assert!(!scanner.excluded(Path::new("/src")));
+    }
+
+    #[test]
+    fn include_with_exclude() {
+        let mut scanner = scanner(ScanOptions {
+            include: vec!["**/*.rs".into()],
+            exclude: vec!["target/**".into()],
+            ..Default::default()
+        });
+        scanner.root = PathBuf::from("/src");
+        assert!(scanner.included(Path::new("/src/main.rs")));
+        assert!(scanner.included(Path::new("/src/a/b.rs")));
+        assert!(!scanner.included(Path::new("/src/a")));
+        // excludes are checked first in visit()
+        assert!(scanner.included(Path::new("/src/target/x.rs")));
+        assert!(scanner.excluded(Path::new("/src/target/x.rs")));
+
+        let scanner = self::scanner(ScanOptions::default());
+        assert!(scanner.included(Path::new("anything")));
+    }
+
+    #[test]
+    fn max_depth_levels() {
+        let scanner = self::scanner(ScanOptions::default());
+        assert!(scanner.within_depth(1000));
+
+        // 0: only the root banner and total, no entries at all
+        let scanner = self::scanner(ScanOptions { max_depth: Some(0), ..Default::default() });
+        assert!(scanner.within_depth(0));
+        assert!(!scanner.within_depth(1));
+
+        // 1: the root's children, subdirectories listed but not entered
+        let scanner = self::scanner(ScanOptions { max_depth: Some(1), ..Default::default() });
+        assert!(scanner.within_depth(1));
+        assert!(!scanner.within_depth(2));
+    }
+
+    #[test]
+    fn exclude_bad_pattern() {
+        let opts = ScanOptions { exclude: vec!["a[b".into()], ..Default::default() };
+        assert!(Scanner::new(opts).is_err());
+    }
+
+    #[test]
+    fn root_jobs_with_max_files() {
+        let opts = ScanOptions { root_jobs: 2, max_files: Some(10), ..Default::default() };
+        assert!(matches!(Scanner::new(opts), Err(Error::Conflict("--root-jobs", "--max-files"))));
+        assert!(Scanner::new(ScanOptions { root_jobs: 1, max_files: Some(10), ..Default::default() }).is_ok());
+    }
+
+    #[test]
+    fn scan_path_entries() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("b.txt"), "hello\n").unwrap();
+        std::fs::create_dir(tmp.path().join("a")).unwrap();
+        std::fs::write(tmp.path().join("a/c"), "").unwrap();
+
+        let entries: Vec<ScanEntry> = scan_path(tmp.path(), &ScanOptions::default()).unwrap().collect();
+        let paths: Vec<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["a", "b.txt", "a/c"]);
+
+        assert_eq!(entries[0].kind, Kind::Dir);
+        assert_eq!(entries[1].kind, Kind::File);
+        assert_eq!(entries[1].size, 6);
+        assert_eq!(entries[1].hash, "b1946ac9");
+        assert_eq!(entries[2].hash, "empty");
+        assert!(entries[1].has_digest());
+        assert!(!entries[2].has_digest());
+
+        let opts = ScanOptions { no_hash: true, ..Default::default() };
+        let entry = scan_path(tmp.path(), &opts).unwrap().nth(1).unwrap();
+        assert_eq!(entry.hash, "--------");
+        let opts = ScanOptions { stat_only: true, ..Default::default() };
+        let entry = scan_path(tmp.path(), &opts).unwrap().nth(1).unwrap();
+        assert_eq!(entry.hash, "--------");
+
+        let opts = ScanOptions { hash_empty: true, ..Default::default() };
+        let entry = scan_path(tmp.path(), &opts).unwrap().nth(2).unwrap();
+        assert_eq!(entry.hash, "d41d8cd9");
+        assert!(entry.has_digest());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follow_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("d")).unwrap();
+        std::fs::write(tmp.path().join("d/f"), "hello\n").unwrap();
+        symlink("d", tmp.path().join("ld")).unwrap();
+        symlink("d/f", tmp.path().join("lf")).unwrap();
+        symlink("..", tmp.path().join("d/up")).unwrap();
+
+        let paths = |opts: &ScanOptions| -> Vec<String> {
+            scan_path(tmp.path(), opts).unwrap().map(|e| e.path).collect()
+        };
+
+        // by default links are listed but not entered
+        assert_eq!(paths(&ScanOptions::default()), ["d", "ld", "lf", "d/f", "d/up"]);
+
+        // following: ld is the same directory as d and d/up loops back
+        // to the root, so neither is entered
+        let opts = ScanOptions { follow_symlinks: true, ..Default::default() };
+        assert_eq!(paths(&opts), ["d", "ld", "lf", "d/f", "d/up"]);
+
+        let lf = scan_path(tmp.path(), &opts).unwrap().find(|e| e.path == "lf").unwrap();
+        assert_eq!(lf.kind, Kind::Symlink);
+        assert_eq!(lf.size, 6);
+        assert_eq!(lf.hash, "b1946ac9");
+
+        // with d excluded the link is the only way in
+        let opts = ScanOptions { follow_symlinks: true, exclude: vec!["d".into()], ..Default::default() };
+        assert_eq!(paths(&opts), ["ld", "lf", "ld/f", "ld/up"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follow_only() {
+        use std::os::unix::fs::symlink;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("d")).unwrap();
+        std::fs::write(tmp.path().join("d/f"), "").unwrap();
+        std::fs::write(outside.path().join("g"), "").unwrap();
+        symlink("d", tmp.path().join("in")).unwrap();
+        symlink(outside.path(), tmp.path().join("out")).unwrap();
+        symlink("..", tmp.path().join("d/up")).unwrap();
+
+        let paths = |follow_only: &[&str]| -> Vec<String> {
+            let follow_only = follow_only.iter().map(|glob| glob.to_string()).collect();
+            let opts = ScanOptions { follow_only, exclude: vec!["d".into()], ..Default::default() };
+            scan_path(tmp.path(), &opts).unwrap().map(|e| e.path).collect()
+        };
+        assert_eq!(paths(&[]), ["in", "out"]);
+        // by the link's own path
+        assert_eq!(paths(&["in"]), ["in", "out", "in/f", "in/up"]);
+        // by where it points; up leads back to the root, so isn't entered
+        assert_eq!(paths(&["d", ".."]), ["in", "out", "in/f", "in/up"]);
+        assert_eq!(paths(&["/**"]), ["in", "out", "out/g"]);
+    }
+
+    #[test]
+    fn changed_during_scan() {
+        let tmp = tempfile::tempdir().unwrap();
+        let names = ["same", "shorter", "longer"];
+        for name in names {
+            std::fs::write(tmp.path().join(name), "hello\n").unwrap();
+        }
+
+        let mut changed = scanner(ScanOptions::default());
+        let mut stat = Vec::new();
+        for name in names {
+            let path = tmp.path().join(name);
+            let (entry, len) = changed.report(&path);
+            changed.queue.push_back(Event::Entry(Box::new(entry)));
+            stat.push((path, len.unwrap()));
+        }
+        // truncated and extended between stat and read
+        std::fs::write(tmp.path().join("shorter"), "hi\n").unwrap();
+        std::fs::write(tmp.path().join("longer"), "hello again\n").unwrap();
+        for (index, (path, len)) in stat.into_iter().enumerate() {
+            let hashed = hash::hash_file(&path, HashAlgo::Md5, hash::DEFAULT_CHUNK, None, false, None);
+            changed.settle_hash(index, path, len, hashed);
+        }
+
+        let notes: Vec<Vec<String>> = changed.by_ref().filter_map(|event| match event {
+            Event::Entry(entry) => Some(entry.notes),
+            _ => None,
+        }).collect();
+        assert_eq!(notes, [vec![], vec!["changed during scan".to_string()], vec!["changed during scan".into()]]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_totals() {
+        use std::os::unix::fs::symlink;
+
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("f"), "hello\n").unwrap();
+        symlink("f", tmp.path().join("l")).unwrap();
+
+        let total = |opts: ScanOptions| {
+            let mut scanner = Scanner::new(opts).unwrap();
+            scanner.add_root(tmp.path());
+            scanner.by_ref().for_each(drop);
+            scanner.summary().bytes
+        };
+        assert_eq!(total(ScanOptions::default()), 6);
+        assert_eq!(total(ScanOptions { count_symlinks: true, ..Default::default() }), 7);
+        assert_eq!(total(ScanOptions { follow_symlinks: true, ..Default::default() }), 12);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn deref_info() {
+        use std::os::unix::fs::symlink;
+
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("f"), "hello\n").unwrap();
+        symlink("f", tmp.path().join("l")).unwrap();
+        symlink(".", tmp.path().join("m")).unwrap();
+        symlink("gone", tmp.path().join("n")).unwrap();
+
+        let opts = ScanOptions { deref_info: true, ..Default::default() };
+        let notes: Vec<Vec<String>> = scan_path(tmp.path(), &opts).unwrap().map(|e| e.notes).collect();
+        assert_eq!(notes, [vec![], vec!["-> file, 6 bytes".to_string()], vec!["-> dir".into()], vec!["dangling".into()]]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn allocated_sizes() {
+        let tmp = tempfile::tempdir().unwrap();
+        // a hole with one byte after it: most of it takes no space
+        let file = std::fs::File::create(tmp.path().join("sparse")).unwrap();
+        file.set_len(1 << 24).unwrap();
+        drop(file);
+
+        let size = |blocks| {
+            let opts = ScanOptions { blocks, ..Default::default() };
+            scan_path(tmp.path(), &opts).unwrap().next().unwrap().size
+        };
+        assert_eq!(size(false), 1 << 24);
+        assert!(size(true) < 1 << 24);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hardlinks() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a"), "hello\n").unwrap();
+        std::fs::hard_link(tmp.path().join("a"), tmp.path().join("b")).unwrap();
+        std::fs::write(tmp.path().join("c"), "x").unwrap();
+
+        let scan = |opts: ScanOptions| -> (Vec<ScanEntry>, u64) {
+            let mut scanner = Scanner::new(opts).unwrap();
+            scanner.add_root(tmp.path());
+            let mut entries = Vec::new();
+            let mut total = 0;
+            for event in scanner {
+                match event {
+                    Event::Entry(entry) => entries.push(*entry),
+                    Event::RootEnd(bytes) => total = bytes,
+                    _ => {}
+                }
+            }
+            (entries, total)
+        };
+
+        let (entries, total) = scan(ScanOptions::default());
+        assert!(entries[0].notes.is_empty());
+        assert_eq!(entries[1].notes, ["hardlink"]);
+        assert!(entries[2].notes.is_empty());
+        assert_eq!(total, 13);
+
+        let (entries, total) = scan(ScanOptions { dedup_hardlinks: true, ..Default::default() });
+        assert_eq!(entries[1].notes, ["hardlink"]);
+        assert_eq!(entries[1].size, 6);
+        assert_eq!(total, 7);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sort_keys() {
+        use std::time::{Duration, SystemTime};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let epoch = SystemTime::UNIX_EPOCH;
+        for (name, body, age) in [("a", "xxx", 3), ("b", "x", 1), ("c", "xxx", 2), ("d", "", 1)] {
+            let path = tmp.path().join(name);
+            std::fs::write(&path, body).unwrap();
+            let file = std::fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(epoch + Duration::from_secs(1000 - age)).unwrap();
+        }
+        std::fs::create_dir(tmp.path().join("e")).unwrap();
+        std::fs::File::open(tmp.path().join("e")).unwrap()
+            .set_modified(epoch).unwrap();
+
+        let order = |sort| -> Vec<String> {
+            let opts = ScanOptions { sort, ..Default::default() };
+            scan_path(tmp.path(), &opts).unwrap().map(|e| e.name).collect()
+        };
+
+        assert_eq!(order(SortKey::Name), ["a", "b", "c", "d", "e"]);
+        // ties on size or mtime are broken by name
+        assert_eq!(order(SortKey::Size), ["d", "e", "b", "a", "c"]);
+        assert_eq!(order(SortKey::Mtime), ["e", "a", "c", "b", "d"]);
+        let mut none = order(SortKey::None);
+        none.sort();
+        assert_eq!(none, ["a", "b", "c", "d", "e"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn octal_modes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("x");
+        std::fs::write(&path, "").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o4751)).unwrap();
+
+        let mode = |mode_format| {
+            let opts = ScanOptions { mode_format, ..Default::default() };
+            scan_path(tmp.path(), &opts).unwrap().next().unwrap().mode.unwrap()
+        };
+        assert_eq!(mode(ModeFormat::Symbolic), "-rwsr-x--x");
+        assert_eq!(mode(ModeFormat::Octal), "4751");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn numeric_ids() {
+        use std::os::unix::fs::MetadataExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("x"), "").unwrap();
+        let meta = tmp.path().join("x").metadata().unwrap();
+
+        let opts = ScanOptions { numeric_ids: true, ..Default::default() };
+        let entry = scan_path(tmp.path(), &opts).unwrap().next().unwrap();
+        assert_eq!(entry.owner, meta.uid().to_string());
+        assert_eq!(entry.group, meta.gid().to_string());
+        assert_eq!(entry.inode, None);
+
+        let opts = ScanOptions { show_inodes: true, ..Default::default() };
+        let entry = scan_path(tmp.path(), &opts).unwrap().next().unwrap();
+        assert_eq!(entry.inode, Some(meta.ino()));
+        assert!(serde_json::to_string(&entry).unwrap().contains(&format!(r#""inode":{}"#, meta.ino())));
+    }
+
+    #[test]
+    fn full_hash_ignores_size_limit() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("big"), "hello\n").unwrap();
+
+        let hash = |full_hash| {
+            let opts = ScanOptions { max_sum_size: 4, full_hash, ..Default::default() };
+            scan_path(tmp.path(), &opts).unwrap().next().unwrap().hash
+        };
+        assert_eq!(hash(false), "skipped");
+        assert_eq!(hash(true), "b1946ac9");
+    }
+
+    #[test]
+    fn head_hash() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("big"), "hello\n").unwrap();
+        std::fs::write(tmp.path().join("small"), "hi").unwrap();
+
+        // also past the size limit, and without a change being noted
+        let opts = ScanOptions { max_sum_size: 4, head_hash: Some(4), ..Default::default() };
+        let entries: Vec<ScanEntry> = scan_path(tmp.path(), &opts).unwrap().collect();
+        // md5 of "hell"
+        assert_eq!(entries[0].hash, "~4229d691");
+        assert!(entries[0].notes.is_empty());
+        assert!(!entries[0].has_digest());
+        assert_eq!(entries[1].hash, "~49f68a5c");
+    }
+
+    #[test]
+    fn parallel_hashing() {
+        let tmp = tempfile::tempdir().unwrap();
+        for i in 0..20 {
+            std::fs::write(tmp.path().join(format!("f{:02}", i)), i.to_string()).unwrap();
+        }
+
+        let hashes = |jobs| -> Vec<(String, String)> {
+            let opts = ScanOptions { jobs, ..Default::default() };
+            scan_path(tmp.path(), &opts).unwrap().map(|e| (e.path, e.hash)).collect()
+        };
+        let serial = hashes(1);
+        assert_eq!(serial[3], ("f03".to_string(), "eccbc87e".to_string()));
+        assert_eq!(hashes(4), serial);
+    }
+
+    #[test]
+    fn read_ahead() {
+        let tmp = tempfile::tempdir().unwrap();
+        for i in 0..6 {
+            for j in 0..3 {
+                let dir = tmp.path().join(format!("d{}/e{}", i, j));
+                std::fs::create_dir_all(&dir).unwrap();
+                std::fs::write(dir.join("f"), format!("{} {}", i, j)).unwrap();
+            }
+        }
+
+        for tree_order in [false, true] {
+            let paths = |threads| -> Vec<(String, String)> {
+                let opts = ScanOptions { threads, tree_order, ..Default::default() };
+                scan_path(tmp.path(), &opts).unwrap().map(|e| (e.path, e.hash)).collect()
+            };
+            let serial = paths(1);
+            assert_eq!(serial.len(), 6 + 18 + 18);
+            assert_eq!(paths(3), serial);
+        }
+    }
+
+    #[test]
+    fn resume() {
+        let tmp = tempfile::tempdir().unwrap();
+        for dir in ["a/deep", "b", "c"] {
+            std::fs::create_dir_all(tmp.path().join(dir)).unwrap();
+            std::fs::write(tmp.path().join(dir).join("f"), "").unwrap();
+        }
+        let other = tempfile::tempdir().unwrap();
+
+        // stop partway through b: a's subtree is done, b's isn't
+        let mut first = scanner(ScanOptions::default());
+        first.add_root(tmp.path());
+        first.add_root(other.path());
+        first.by_ref().find(|event| matches!(event, Event::Dir(dir) if dir == Path::new("b"))).unwrap();
+        let checkpoint = first.checkpoint().clone();
+        assert!(checkpoint.dir_done(tmp.path(), Path::new("a")));
+        assert!(!checkpoint.dir_done(tmp.path(), Path::new("b")));
+        assert!(!checkpoint.root_done(tmp.path()));
+
+        let mut resumed = scanner(ScanOptions::default());
+        resumed.resume(checkpoint);
+        resumed.add_root(tmp.path());
+        resumed.add_root(other.path());
+        let paths: Vec<String> = resumed.by_ref().filter_map(|event| match event {
+            Event::Entry(entry) => Some(entry.path),
+            _ => None,
+        }).collect();
+        assert_eq!(paths, ["a", "b", "c", "b/f", "c/f"]);
+        assert!(resumed.checkpoint().root_done(tmp.path()));
+        assert!(resumed.checkpoint().root_done(other.path()));
+
+        // all done: nothing more to report
+        let mut again = scanner(ScanOptions::default());
+        again.resume(resumed.checkpoint().clone());
+        again.add_root(tmp.path());
+        assert_eq!(again.count(), 0);
+    }
+
+    #[test]
+    fn parallel_roots() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut roots = Vec::new();
+        for i in 0..5 {
+            let root = tmp.path().join(format!("r{}", i));
+            std::fs::create_dir_all(root.join("sub")).unwrap();
+            std::fs::write(root.join("sub/f"), "x".repeat(i)).unwrap();
+            roots.push(root);
+        }
+        roots.insert(2, tmp.path().join("gone"));
+
+        let scan = |root_jobs| {
+            let mut scanner = scanner(ScanOptions { root_jobs, ..Default::default() });
+            for root in &roots {
+                scanner.add_root(root);
+            }
+            let events: Vec<String> = scanner.by_ref().map(|event| format!("{:?}", event)).collect();
+            (events, format!("{:?}", scanner.summary()), scanner.errors())
+        };
+        let serial = scan(1);
+        assert_eq!(serial.2, 1);
+        assert_eq!(scan(2), serial);
+        assert_eq!(scan(8), serial);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn special_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let status = std::process::Command::new("mkfifo").arg(tmp.path().join("fifo")).status().unwrap();
+        assert!(status.success());
+        let _sock = std::os::unix::net::UnixListener::bind(tmp.path().join("sock")).unwrap();
+
+        let entries: Vec<ScanEntry> = scan_path(tmp.path(), &ScanOptions::default()).unwrap().collect();
+        assert_eq!(entries[0].kind, Kind::Special);
+        assert_eq!(entries[0].notes, ["fifo"]);
+        assert_eq!(entries[0].hash, "");
+        assert_eq!(entries[1].notes, ["socket"]);
+
+        assert_eq!(special_kind(0o020666), Some("char device"));
+        assert_eq!(special_kind(0o060660), Some("block device"));
+        assert_eq!(special_kind(0o100644), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn special_bits_noted() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        for (name, mode) in [("plain", 0o755), ("suid", 0o4755), ("sgid", 0o2755)] {
+            std::fs::write(tmp.path().join(name), "").unwrap();
+            std::fs::set_permissions(tmp.path().join(name), std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+        std::fs::create_dir(tmp.path().join("tmp")).unwrap();
+        std::fs::set_permissions(tmp.path().join("tmp"), std::fs::Permissions::from_mode(0o1777)).unwrap();
+
+        let notes = |special_bits| -> Vec<Vec<String>> {
+            let opts = ScanOptions { special_bits, ..Default::default() };
+            scan_path(tmp.path(), &opts).unwrap().map(|e| e.notes).collect()
+        };
+        assert_eq!(notes(true), [vec![], vec!["setgid".to_string()], vec!["setuid".into()], vec!["sticky".into()]]);
+        assert!(notes(false).iter().all(Vec::is_empty));
+        assert_eq!(special_bits(0o7000).collect::<Vec<_>>(), ["setuid", "setgid", "sticky"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn owner_filters() {
+        use std::os::unix::fs::MetadataExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("d")).unwrap();
+        std::fs::write(tmp.path().join("d/e"), "").unwrap();
+        let meta = tmp.path().metadata().unwrap();
+
+        let paths = |owner: Option<String>, group: Option<String>| -> Vec<String> {
+            let opts = ScanOptions { owner, group, ..Default::default() };
+            scan_path(tmp.path(), &opts).unwrap().map(|entry| entry.path).collect()
+        };
+        assert_eq!(paths(Some(meta.uid().to_string()), None), ["d", "d/e"]);
+        assert_eq!(paths(None, Some(meta.gid().to_string())), ["d", "d/e"]);
+        if let Some(name) = platform::user_name(meta.uid()) {
+            assert_eq!(paths(Some(name), None), ["d", "d/e"]);
+        }
+        assert!(paths(Some((meta.uid() + 1).to_string()), None).is_empty());
+        assert!(paths(None, Some("no-such-group".into())).is_empty());
+        assert!(id_matches("0", 0, "root", false));
+        assert!(!id_matches("root", 1, "daemon", false));
+        assert!(!id_matches("Root", 0, "root", false));
+        assert!(id_matches("Root", 0, "root", true));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mode_filters() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("d")).unwrap();
+        for (name, mode) in [("a", 0o644), ("b", 0o666), ("d/c", 0o4755)] {
+            std::fs::write(tmp.path().join(name), "").unwrap();
+            std::fs::set_permissions(tmp.path().join(name), std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+        std::fs::set_permissions(tmp.path().join("d"), std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let paths = |mode_mask, mode_match| -> Vec<String> {
+            let opts = ScanOptions { mode_mask: Some(mode_mask), mode_match, ..Default::default() };
+            scan_path(tmp.path(), &opts).unwrap().map(|entry| entry.path).collect()
+        };
+        assert_eq!(paths(0o002, None), ["b"]);
+        // d itself doesn't match, but is still descended
+        assert_eq!(paths(0o4000, None), ["d/c"]);
+        assert_eq!(paths(0o022, None), ["b"]);
+        assert_eq!(paths(0o022, Some(0o020)), Vec::<String>::new());
+        assert_eq!(paths(0o066, Some(0o044)), ["a", "d", "d/c"]);
+    }
+
+    #[test]
+    fn retries() {
+        let flaky = |fails: u32| {
+            let mut calls = 0;
+            move || {
+                calls += 1;
+                if calls <= fails { Err(io::Error::other("timed out")) } else { Ok(calls) }
+            }
+        };
+        assert!(retrying(0, flaky(1)).is_err());
+        assert_eq!(retrying(2, flaky(2)).unwrap(), 3);
+        assert!(retrying(1, flaky(2)).is_err());
+
+        let mut calls = 0;
+        let res: io::Result<()> = retrying(3, || {
+            calls += 1;
+            Err(io::ErrorKind::NotFound.into())
+        });
+        assert!(res.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn retried_hash_gives_up() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("gone");
+        std::fs::write(&path, "hello\n").unwrap();
+
+        let mut retried = scanner(ScanOptions { retries: 3, ..Default::default() });
+        let (entry, len) = retried.report(&path);
+        retried.queue.push_back(Event::Entry(Box::new(entry)));
+        std::fs::remove_file(&path).unwrap();
+        let started = std::time::Instant::now();
+        retried.settle_hash(0, path.clone(), len.unwrap(), Err(io::Error::other("timed out")));
+
+        // retried after the timeout, then stopped at the missing file
+        assert!(started.elapsed() >= RETRY_DELAY && started.elapsed() < RETRY_DELAY * 3);
+        let events: Vec<Event> = retried.by_ref().collect();
+        assert!(matches!(&events[0], Event::Entry(entry) if entry.hash == "error"));
+        assert!(matches!(&events[1], Event::Unreadable(_, e) if e.kind() == io::ErrorKind::NotFound));
+        assert_eq!(retried.errors(), 1);
+    }
+
+    #[test]
+    fn reused_digests() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a"), "hello\n").unwrap();
+        std::fs::write(tmp.path().join("b"), "world\n").unwrap();
+        let before: Vec<ScanEntry> = scan_path(tmp.path(), &ScanOptions::default()).unwrap().collect();
+
+        let known = |path: &str, hash: &str| {
+            let entry = before.iter().find(|entry| entry.path == path).unwrap();
+            (path.to_string(), KnownDigest {
+                size: entry.size, mtime_ns: entry.mtime_ns.unwrap(), algo: HashAlgo::Md5, hash: hash.into(),
+            })
+        };
+        let mut first = scanner(ScanOptions::default());
+        // b's digest was made with a different length, so it's no use
+        first.reuse_digests(HashMap::from([known("a", "0123abcd"), known("b", "0123")]));
+        first.add_root(tmp.path());
+        let hashes: Vec<String> = first.by_ref().filter_map(|event| match event {
+            Event::Entry(entry) => Some(entry.hash),
+            _ => None,
+        }).collect();
+        assert_eq!(hashes, ["0123abcd", before[1].hash.as_str()]);
+        assert_eq!(first.summary().hashed, 6);
+
+        // a different size means it's changed
+        let mut again = scanner(ScanOptions::default());
+        again.reuse_digests(HashMap::from([(String::from("a"), KnownDigest { size: 1, ..known("a", "0123abcd").1 })]));
+        again.add_root(tmp.path());
+        assert!(again.any(|event| matches!(event, Event::Entry(entry) if entry.hash == before[0].hash)));
+
+        // nor does an mtime a nanosecond out, or a digest by another algorithm
+        let (_, a) = known("a", "0123abcd");
+        for known in [KnownDigest { mtime_ns: a.mtime_ns + 1, ..a.clone() }, KnownDigest { algo: HashAlgo::Sha1, ..a }] {
+            let mut again = scanner(ScanOptions::default());
+            again.reuse_digests(HashMap::from([(String::from("a"), known)]));
+            again.add_root(tmp.path());
+            assert!(again.any(|event| matches!(event, Event::Entry(entry) if entry.hash == before[0].hash)));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn snapshot_ids() {
+        use std::os::unix::fs::MetadataExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("x"), "").unwrap();
+        let meta = tmp.path().join("x").metadata().unwrap();
+
+        let mut snapshot = scanner(ScanOptions { snapshot_ids: true, ..Default::default() });
+        let looked_up = scanner(ScanOptions::default()).user_name(meta.uid()).to_string();
+        assert_eq!(snapshot.user_name(meta.uid()), looked_up);
+        // nothing's looked up once the names have been read
+        assert!(!snapshot.lookup_ids);
+        let unknown = (0..u32::MAX).find(|uid| !snapshot.users.contains_key(uid)).unwrap();
+        assert_eq!(snapshot.user_name(unknown), "?");
+    }
+
+    #[test]
+    fn size_filters() {
+        let tmp = tempfile::tempdir().unwrap();
+        for (name, len) in [("a", 1), ("b", 10), ("c", 100)] {
+            std::fs::write(tmp.path().join(name), "x".repeat(len)).unwrap();
+        }
+        std::fs::create_dir(tmp.path().join("d")).unwrap();
+        std::fs::write(tmp.path().join("d/e"), "x".repeat(20)).unwrap();
+
+        let mut scanner = Scanner::new(ScanOptions {
+            min_size: Some(5),
+            max_size: Some(50),
+            ..Default::default()
+        }).unwrap();
+        scanner.add_root(tmp.path());
+
+        let mut paths = Vec::new();
+        let mut total = 0;
+        for event in scanner {
+            match event {
+                Event::Entry(entry) => paths.push(entry.path),
+                Event::RootEnd(bytes) => total = bytes,
+                _ => {}
+            }
+        }
+        assert_eq!(paths, ["b", "d", "d/e"]);
+        assert_eq!(total, 30);
+    }
+
+    #[test]
+    fn time_filters() {
+        use std::time::Duration;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let day = |n: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(n * 86400);
+        for (name, n) in [("a", 1), ("b", 2), ("d/c", 3)] {
+            std::fs::create_dir_all(tmp.path().join(name).parent().unwrap()).unwrap();
+            let file = std::fs::File::create(tmp.path().join(name)).unwrap();
+            file.set_len(n).unwrap();
+            file.set_modified(day(n)).unwrap();
+        }
+
+        let opts = ScanOptions { since: Some(day(2)), until: Some(day(2)), ..Default::default() };
+        let mut scanner = Scanner::new(opts).unwrap();
+        scanner.add_root(tmp.path());
+        let paths: Vec<String> = scanner.by_ref()
+            .filter_map(|event| match event { Event::Entry(entry) => Some(entry.path), _ => None })
+            .collect();
+        assert_eq!(paths, ["b", "d"]);
+        assert_eq!(scanner.summary().bytes, 2);
+
+        let opts = ScanOptions { since: Some(day(3)), ..Default::default() };
+        let paths: Vec<String> = scan_path(tmp.path(), &opts).unwrap().map(|e| e.path).collect();
+        assert_eq!(paths, ["d", "d/c"]);
+    }
+
+    #[test]
+    fn empties() {
+        let tmp = tempfile::tempdir().unwrap();
+        for dir in ["full/empty", "bare"] {
+            std::fs::create_dir_all(tmp.path().join(dir)).unwrap();
+        }
+        std::fs::write(tmp.path().join("full/zero"), "").unwrap();
+        std::fs::write(tmp.path().join("full/one"), "1").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("nowhere", tmp.path().join("link")).unwrap();
+
+        let mut scanner = Scanner::new(ScanOptions { empty: true, ..Default::default() }).unwrap();
+        scanner.add_root(tmp.path());
+        let paths: Vec<String> = scanner.by_ref()
+            .filter_map(|event| match event { Event::Entry(entry) => Some(entry.path), _ => None })
+            .collect();
+        assert_eq!(paths, ["bare", "full/empty", "full/zero"]);
+        assert_eq!(scanner.summary().empty, 3);
+    }
+
+    #[test]
+    fn archive_members() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("d")).unwrap();
+        let mut tar = tar::Builder::new(std::fs::File::create(tmp.path().join("d/t.tar")).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(6);
+        tar.append_data(&mut header, "a", &b"hello\n"[..]).unwrap();
+        tar.into_inner().unwrap();
+        std::fs::write(tmp.path().join("d/bad.zip"), "not a zip").unwrap();
+
+        let mut scanner = scanner(ScanOptions { into_archives: true, ..Default::default() });
+        scanner.add_root(tmp.path());
+        let mut entries = Vec::new();
+        for event in scanner.by_ref() {
+            if let Event::Entry(entry) = event {
+                entries.push((entry.path, entry.name, entry.hash));
+            }
+        }
+        assert_eq!(entries[3], ("d/t.tar!a".into(), "t.tar!a".into(), "b1946ac9".into()));
+        assert_eq!(entries.len(), 4);
+        // members don't count twice
+        assert_eq!(scanner.summary().files, 2);
+        assert_eq!(scanner.errors(), 1);
+    }
+
+    #[test]
+    fn max_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        for name in ["a/b/c", "a/d", "e"] {
+            std::fs::create_dir_all(tmp.path().join(name).parent().unwrap()).unwrap();
+            std::fs::write(tmp.path().join(name), "x").unwrap();
+        }
+
+        let scan = |max_files, tree_order| {
+            let mut scanner = scanner(ScanOptions { max_files, tree_order, ..Default::default() });
+            scanner.add_root(tmp.path());
+            scanner.add_root(tmp.path());
+            let mut paths = Vec::new();
+            let mut ends = 0;
+            for event in scanner.by_ref() {
+                match event {
+                    Event::Entry(entry) => paths.push(entry.path),
+                    Event::RootEnd(_) => ends += 1,
+                    _ => {}
+                }
+            }
+            (paths, ends, scanner.truncated())
+        };
+        assert_eq!(scan(Some(4), false), (vec!["a".into(), "e".into(), "a/b".into(), "a/d".into()], 1, true));
+        // whole listings are counted before any is descended
+        assert_eq!(scan(Some(2), true), (vec!["a".into(), "e".into()], 1, true));
+        assert!(!scan(Some(12), false).2);
+    }
+
+    #[test]
+    fn max_files_prefetched() {
+        let tmp = tempfile::tempdir().unwrap();
+        for dir in 0..20 {
+            for file in 0..10 {
+                let path = tmp.path().join(format!("d{:02}/f{}", dir, file));
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, "x").unwrap();
+            }
+        }
+
+        let mut scanner = scanner(ScanOptions { max_files: Some(25), threads: 4, ..Default::default() });
+        scanner.add_root(tmp.path());
+        scanner.by_ref().for_each(drop);
+        assert!(scanner.truncated());
+        // the listings read ahead for the directories dropped aren't still waited on
+        assert_eq!(scanner.prefetch.as_ref().unwrap().outstanding(), 0);
+    }
+
+    #[test]
+    fn time_formats() {
+        let scanner = self::scanner(ScanOptions::default());
+        let t = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1656676805);
+        assert_eq!(scanner.format_time(Some(t)), "2022-07-01T12:00");
+        assert_eq!(scanner.format_time(None), "?");
+
+        let scanner = self::scanner(ScanOptions { time_format: "%s %Y".into(), ..Default::default() });
+        assert_eq!(scanner.format_time(Some(t)), "1656676805 2022");
+    }
+
+    #[test]
+    fn time_lists() {
+        assert_eq!(TimeKind::parse("m"), Ok(TimeKind::Modified));
+        assert_eq!(TimeKind::parse("c"), Ok(TimeKind::Changed));
+        assert!(TimeKind::parse("x").is_err());
+        assert!(TimeKind::parse("").is_err());
+
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("f"), "").unwrap();
+        let opts = ScanOptions { times: vec![TimeKind::Accessed], ..Default::default() };
+        let entry = scan_path(tmp.path(), &opts).unwrap().next().unwrap();
+        assert!(!entry.mtime.is_empty());
+        assert!(entry.atime.is_some());
+        assert!(entry.ctime.is_none());
+    }
+
+    #[test]
+    fn gitignore() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join(".gitignore"), "*.log\n/build\n").unwrap();
+        std::fs::create_dir_all(tmp.path().join("build")).unwrap();
+        std::fs::write(tmp.path().join("build/out"), "").unwrap();
+        std::fs::create_dir_all(tmp.path().join("sub/build")).unwrap();
+        std::fs::write(tmp.path().join("sub/.gitignore"), "!keep.log\nsecret\n").unwrap();
+        for name in ["a.log", "sub/b.log", "sub/keep.log", "sub/secret", "sub/c"] {
+            std::fs::write(tmp.path().join(name), "").unwrap();
+        }
+
+        let paths = |gitignore| -> Vec<String> {
+            let opts = ScanOptions { gitignore, ..Default::default() };
+            scan_path(tmp.path(), &opts).unwrap().map(|e| e.path).collect()
+        };
+        assert_eq!(paths(true), [".gitignore", "sub", "sub/.gitignore", "sub/build", "sub/c", "sub/keep.log"]);
+        assert_eq!(paths(false).len(), 11);
+    }
+
+    #[test]
+    fn ignore_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join(IGNORE_FILE), "# scratch space\n*.tmp\n!keep.tmp\n/build/\n").unwrap();
+        for dir in ["build", "sub/build"] {
+            std::fs::create_dir_all(tmp.path().join(dir)).unwrap();
+            std::fs::write(tmp.path().join(dir).join("out"), "").unwrap();
+        }
+        for name in ["a.tmp", "keep.tmp", "sub/b.tmp", "sub/c", "d"] {
+            std::fs::write(tmp.path().join(name), "").unwrap();
+        }
+
+        let paths = |ignore_file, exclude: &[&str]| -> Vec<String> {
+            let exclude = exclude.iter().map(|glob| glob.to_string()).collect();
+            let opts = ScanOptions { ignore_file, exclude, ..Default::default() };
+            scan_path(tmp.path(), &opts).unwrap().map(|e| e.path).collect()
+        };
+        assert_eq!(paths(true, &[]), [IGNORE_FILE, "d", "keep.tmp", "sub", "sub/build", "sub/c", "sub/build/out"]);
+        // --exclude still applies, and can't be overridden by the file
+        assert_eq!(paths(true, &["sub", "keep.tmp"]), [IGNORE_FILE, "d"]);
+        assert_eq!(paths(false, &[]).len(), 11);
+
+        // only the root's own file counts
+        let sub = tmp.path().join("sub");
+        std::fs::write(sub.join(IGNORE_FILE), "c\n").unwrap();
+        assert_eq!(paths(true, &[]).len(), 8);
+        let opts = ScanOptions::default();
+        let names: Vec<String> = scan_path(&sub, &opts).unwrap().map(|e| e.path).collect();
+        assert_eq!(names, [IGNORE_FILE, "b.tmp", "build", "build/out"]);
+    }
+
+    #[test]
+    fn ignore_case() {
+        let tmp = tempfile::tempdir().unwrap();
+        for name in ["a.JPG", "b.jpg", "c.Tmp", "d.png"] {
+            std::fs::write(tmp.path().join(name), "").unwrap();
+        }
+
+        let paths = |ignore_case, include: &[&str], exclude: &[&str]| -> Vec<String> {
+            let include = include.iter().map(|glob| glob.to_string()).collect();
+            let exclude = exclude.iter().map(|glob| glob.to_string()).collect();
+            let opts = ScanOptions { ignore_case, include, exclude, ..Default::default() };
+            scan_path(tmp.path(), &opts).unwrap().map(|e| e.path).collect()
+        };
+        assert_eq!(paths(false, &["*.jpg"], &[]), ["b.jpg"]);
+        assert_eq!(paths(true, &["*.jpg"], &[]), ["a.JPG", "b.jpg"]);
+        assert_eq!(paths(false, &[], &["*.JPG", "*.tmp"]), ["b.jpg", "c.Tmp", "d.png"]);
+        assert_eq!(paths(true, &[], &["*.JPG", "*.tmp"]), ["d.png"]);
+
+        std::fs::write(tmp.path().join(IGNORE_FILE), "*.PNG\n").unwrap();
+        assert_eq!(paths(false, &[], &[IGNORE_FILE]).len(), 4);
+        assert_eq!(paths(true, &[], &[IGNORE_FILE]), ["a.JPG", "b.jpg", "c.Tmp"]);
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape_bytes(b"plain/caf\xc3\xa9"), "plain/café");
+        assert_eq!(escape_bytes(b"back\\slash"), r"back\\slash");
+        assert_eq!(escape_bytes(b"new\nline\ttab"), r"new\x0aline\x09tab");
+        assert_eq!(escape_bytes(b"bad\xff\xfe"), r"bad\xff\xfe");
+        // a truncated sequence is escaped byte by byte
+        assert_eq!(escape_bytes(b"\xc3x"), r"\xc3x");
+    }
+
+    #[test]
+    fn xattr_values() {
+        assert_eq!(xattr_text(b"system_u:object_r:etc_t:s0\0"), "system_u:object_r:etc_t:s0");
+        assert_eq!(xattr_text(b"plain"), "plain");
+        assert_eq!(xattr_text(b""), "");
+        assert_eq!(xattr_text(b"\x01\x00\x00\x02"), "0x01000002");
+        assert_eq!(xattr_text(b"a\nb"), "0x610a62");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn xattrs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("x");
+        std::fs::write(&path, "").unwrap();
+        // not every filesystem takes user attributes
+        if xattr::set(&path, "user.test", b"yes").is_err() {
+            return;
+        }
+
+        let opts = ScanOptions { xattrs: true, ..Default::default() };
+        let entry = scan_path(tmp.path(), &opts).unwrap().next().unwrap();
+        assert_eq!(entry.xattrs.get("user.test").map(String::as_str), Some("yes"));
+        let entry = scan_path(tmp.path(), &ScanOptions::default()).unwrap().next().unwrap();
+        assert!(entry.xattrs.is_empty());
+    }
+
+    #[test]
+    fn summary() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("d")).unwrap();
+        std::fs::write(tmp.path().join("d/a.rs"), "fn main() {}\n").unwrap();
+        std::fs::write(tmp.path().join("b.rs"), "").unwrap();
+        std::fs::write(tmp.path().join("Makefile"), "all:\n").unwrap();
+
+        let mut scanner = scanner(ScanOptions::default());
+        scanner.add_root(tmp.path());
+        scanner.add_root(tmp.path().join("d"));
+        scanner.by_ref().for_each(drop);
+
+        let summary = scanner.summary();
+        assert_eq!((summary.files, summary.dirs, summary.symlinks, summary.others), (4, 1, 0, 0));
+        assert_eq!(summary.bytes, 31);
+        assert_eq!(summary.hashed, 31);
+        assert_eq!(summary.extensions.get("rs"), Some(&3));
+        assert_eq!(summary.extensions.get(""), Some(&1));
+        assert_eq!(summary.extension_bytes.get("rs"), Some(&26));
+        assert_eq!(summary.extension_bytes.get(""), Some(&5));
+        assert_eq!(summary.largest, Some((tmp.path().join("d/a.rs"), 13)));
+    }
+
+    #[test]
+    fn tree_order() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tmp.path().join("a/b")).unwrap();
+        for name in ["a/b/f", "a/g", "c", "d/h"] {
+            std::fs::create_dir_all(tmp.path().join(name).parent().unwrap()).unwrap();
+            std::fs::write(tmp.path().join(name), "").unwrap();
+        }
+
+        let paths = |tree_order| -> Vec<String> {
+            let opts = ScanOptions { tree_order, ..Default::default() };
+            scan_path(tmp.path(), &opts).unwrap().map(|e| e.path).collect()
+        };
+        assert_eq!(paths(false), ["a", "c", "d", "a/b", "a/g", "a/b/f", "d/h"]);
+        assert_eq!(paths(true), ["a", "a/b", "a/b/f", "a/g", "c", "d", "d/h"]);
+    }
+
+    #[test]
+    fn resumed_listings() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tmp.path().join("a/b")).unwrap();
+        std::fs::write(tmp.path().join("c"), "").unwrap();
+
+        let mut scanner = scanner(ScanOptions { tree_order: true, ..Default::default() });
+        scanner.add_root(tmp.path());
+        let events: Vec<String> = scanner.map(|event| match event {
+            Event::Root(_) => "root".into(),
+            Event::Dir(dir) => format!("dir {}", dir.display()),
+            Event::DirResume(dir) => format!("resume {}", dir.display()),
+            Event::Entry(entry) => entry.path,
+            Event::DirEnd(_) => "end".into(),
+            Event::RootEnd(_) => "root end".into(),
+            _ => "error".into(),
+        }).collect();
+        assert_eq!(events, ["root", "a", "dir a", "a/b", "dir a/b", "end", "resume a", "end",
+                            "resume ", "c", "end", "root end"]);
+    }
+
+    #[test]
+    fn skip_hidden() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join(".dotroot");
+        std::fs::create_dir_all(root.join(".git/objects")).unwrap();
+        std::fs::create_dir(root.join("src")).unwrap();
+        std::fs::write(root.join("src/.env"), "").unwrap();
+        std::fs::write(root.join("src/main.rs"), "").unwrap();
+
+        let opts = ScanOptions { skip_hidden: true, ..Default::default() };
+        let paths: Vec<String> = scan_path(&root, &opts).unwrap().map(|e| e.path).collect();
+        assert_eq!(paths, ["src", "src/main.rs"]);
+        assert_eq!(scan_path(&root, &ScanOptions::default()).unwrap().count(), 5);
+    }
+
+    #[test]
+    fn strip_components() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tmp.path().join("v1/sub")).unwrap();
+        std::fs::write(tmp.path().join("v1/sub/a"), "").unwrap();
+        std::fs::write(tmp.path().join("top"), "").unwrap();
+
+        let paths = |strip| -> Vec<String> {
+            let opts = ScanOptions { strip_components: strip, ..Default::default() };
+            scan_path(tmp.path(), &opts).unwrap().map(|e| e.path).collect()
+        };
+        assert_eq!(paths(0), ["top", "v1", "v1/sub", "v1/sub/a"]);
+        assert_eq!(paths(1), ["", "", "sub", "sub/a"]);
+        assert_eq!(paths(5), ["", "", "", ""]);
+
+        let mut stripped = scanner(ScanOptions { strip_components: 1, ..Default::default() });
+        stripped.add_root(tmp.path());
+        assert!(stripped.any(|event| matches!(event, Event::Dir(path) if path == Path::new("sub"))));
+    }
+
+    #[test]
+    fn hash_names() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("secret")).unwrap();
+        std::fs::write(tmp.path().join("secret/plan.txt"), "hello\n").unwrap();
+
+        let opts = ScanOptions { hash_names: true, ..Default::default() };
+        let mut scanner = scanner(opts);
+        scanner.add_root(tmp.path());
+        let events: Vec<Event> = scanner.by_ref().collect();
+
+        // md5 of "secret" and "plan.txt", cut to the usual 8 digits
+        let (dir, file) = ("5ebe2294", "1651ce09");
+        let entries: Vec<&ScanEntry> = events.iter().filter_map(|event| match event {
+            Event::Entry(entry) => Some(&**entry),
+            _ => None,
+        }).collect();
+        assert_eq!(entries[0].path, dir);
+        assert_eq!(entries[1].path, format!("{}/{}", dir, file));
+        assert_eq!(entries[1].name, file);
+        assert_eq!(entries[1].hash, "b1946ac9");
+        assert!(events.iter().any(|event| matches!(event, Event::Dir(path) if path == Path::new(dir))));
+        let largest = scanner.summary().largest.clone().unwrap();
+        assert_eq!(largest, (PathBuf::from(format!("{}/{}", dir, file)), 6));
+    }
+
+    #[test]
+    fn unstatable_entry() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut unstatable = scanner(ScanOptions::default());
+        // gone between being listed and being stat'd
+        let (entry, _) = unstatable.report(&tmp.path().join("gone"));
+        assert_eq!(entry.mode, None);
+        assert!(entry.error.as_deref().is_some_and(|error| error.contains("No such file")));
+        assert!(serde_json::to_string(&entry).unwrap().contains(r#""error":"No such file"#));
+        assert!(matches!(unstatable.next(), Some(Event::Unreadable(..))));
+        assert_eq!(unstatable.errors(), 1);
+    }
+
+    #[test]
+    fn unreadable_contents() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("locked");
+        std::fs::write(&path, "hello\n").unwrap();
+
+        let mut unreadable = scanner(ScanOptions::default());
+        let (entry, len) = unreadable.report(&path);
+        unreadable.queue.push_back(Event::Entry(Box::new(entry)));
+        // stat'd, but gone by the time it's opened
+        std::fs::remove_file(&path).unwrap();
+        let hashed = hash::hash_file(&path, HashAlgo::Md5, hash::DEFAULT_CHUNK, None, false, None);
+        unreadable.settle_hash(0, path.clone(), len.unwrap(), hashed);
+
+        let events: Vec<Event> = unreadable.by_ref().collect();
+        let entry = match &events[0] {
+            Event::Entry(entry) => entry,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(entry.hash, "error");
+        assert!(!entry.has_digest());
+        assert!(entry.error.as_deref().is_some_and(|error| error.contains("No such file")));
+        assert!(matches!(&events[1], Event::Unreadable(gone, _) if *gone == path));
+        assert_eq!(unreadable.errors(), 1);
+    }
+
+    #[test]
+    fn missing_root() {
+        let tmp = tempfile::tempdir().unwrap();
+        let gone = tmp.path().join("gone");
+        let mut scanner = scanner(ScanOptions::default());
+        scanner.add_root(&gone);
+        scanner.add_root(tmp.path());
+
+        let events: Vec<Event> = scanner.by_ref().collect();
+        assert!(matches!(&events[0], Event::Unreadable(path, _) if *path == gone));
+        assert!(matches!(&events[1], Event::Root(path) if path == tmp.path()));
+        assert_eq!(scanner.errors(), 1);
+    }
+
+    #[test]
+    fn entry_json() {
+        let entry = ScanEntry {
+            path: "sub/a.txt".into(),
+            name: "a.txt".into(),
+            fs_path: PathBuf::from("/tmp/sub/a.txt"),
+            size: 3,
+            mode: Some("-rw-r--r--".into()),
+            mode_bits: Some(0o100644),
+            owner: "root".into(),
+            group: "root".into(),
+            uid: Some(0),
+            gid: Some(0),
+            inode: None,
+            nlink: None,
+            mtime: "2022-07-01T12:00".into(),
+            mtime_unix: Some(1656676800),
+            mtime_ns: Some(1656676800 * 1_000_000_000),
+            atime: None,
+            ctime: None,
+            hash: "d41d8cd9".into(),
+            hash_algo: Some(HashAlgo::Md5),
+            target: None,
+            kind: Kind::File,
+            xattrs: BTreeMap::new(),
+            notes: Vec::new(),
+            error: None,
+            change: None,
+        };
+        assert_eq!(serde_json::to_string(&entry).unwrap(),
+            r#"{"path":"sub/a.txt","size":3,"mode":"-rw-r--r--","owner":"root","group":"root","mtime":"2022-07-01T12:00","mtime_ns":1656676800000000000,"hash":"d41d8cd9","hash_algo":"md5","target":null,"kind":"file"}"#);
+    }
+
+}
+
+
+// EOF
@@ -1,18 +1,21 @@
 #![allow(dead_code, unused_imports)]
 
 use std::borrow::Cow;
-use std::collections::HashMap;
-use std::ffi::OsString;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write};
 use std::path::{Path, PathBuf};
-use std::os::unix::fs::{PermissionsExt, MetadataExt};
+use std::time::{Duration, Instant, SystemTime};
 
-use clap::Parser;
-use chrono::{DateTime, Local, Utc};
-use walkdir::{Error, Result, WalkDir, DirEntry};
-use md5::{Context};
-use users::{get_user_by_uid, get_group_by_gid};
+use clap::{Parser, ValueEnum};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::Serialize;
+
+use treescan::platform;
+use treescan::{Baseline, Change, Checkpoint, DEFAULT_TIME_FORMAT, Event, HashAlgo, Kind, ModeFormat, ScanEntry, ScanOptions, Scanner, SortKey, Summary, Throttle, TimeKind};
+use treescan::{DupeGroup, find_dupes};
+use treescan::{sum_line, Manifest, Verdict};
 
 
 #[derive(Parser, Debug)]
@@ -30,259 +33,3013 @@ struct Args {
     // #[clap(short, long, parse(from_occurrences))]
     // verbose: u8,
 
+    /// Only hash files smaller than this many MiB
     #[clap(short, long, default_value_t = 3)]
     maxsumsize: u64,
 
     #[clap(long, default_value_t = 8)]
     hashlen: u32,
 
+    /// Hash algorithm for file contents
+    #[clap(long, value_enum, default_value_t = HashAlgo::Md5)]
+    hash: HashAlgo,
+
+    /// Skip paths matching GLOB, relative to the root (repeatable;
+    /// use `**/name` to match at any depth)
+    #[clap(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Only report paths matching GLOB (repeatable); directories are
+    /// still descended, and --exclude wins over --include
+    #[clap(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Also skip paths ignored by `.gitignore` files found below each
+    /// root, and by Git's global excludes file
+    #[clap(long)]
+    gitignore: bool,
+
+    /// Skip files and directories whose names start with a dot, along
+    /// with everything under them; roots are scanned regardless
+    #[clap(long)]
+    skip_hidden: bool,
+
+    /// Don't skip what a `.treescanignore` file at the top of each root
+    /// matches; its rules are in gitignore syntax, and apply along with
+    /// --exclude
+    #[clap(long)]
+    no_ignore_file: bool,
+
+    /// Match --include, --exclude and --quiet-errors-for globs, ignore
+    /// files, and --owner and --group names regardless of case; what's
+    /// shown is unchanged
+    #[clap(long)]
+    ignore_case: bool,
+
+    /// Report entries at most N levels below each root, counted like
+    /// walkdir/find: 1 is the root's own contents, 0 lists nothing
+    #[clap(long, value_name = "N")]
+    max_depth: Option<u32>,
+
+    /// Only report files of at least SIZE bytes (K, M, G, T suffixes
+    /// are powers of 1024)
+    #[clap(long, value_name = "SIZE", value_parser = parse_size)]
+    min_size: Option<u64>,
+
+    /// Only report files of at most SIZE bytes
+    #[clap(long, value_name = "SIZE", value_parser = parse_size)]
+    max_size: Option<u64>,
+
+    /// Only report files modified at or after WHEN: a date, a date and
+    /// time (UTC unless an offset is given), or an age such as 2d
+    /// (s, m, h, d or w ago)
+    #[clap(long, value_name = "WHEN", value_parser = parse_when)]
+    since: Option<SystemTime>,
+
+    /// Only report files modified at or before WHEN, given as for --since
+    #[clap(long, value_name = "WHEN", value_parser = parse_when)]
+    until: Option<SystemTime>,
+
+    /// Only report entries owned by USER, a name or numeric uid;
+    /// directories are still descended
+    #[clap(long, value_name = "USER")]
+    owner: Option<String>,
+
+    /// Only report entries whose group is GROUP, a name or numeric gid
+    #[clap(long, value_name = "GROUP")]
+    group: Option<String>,
+
+    /// Only report entries with every permission bit of the octal MASK
+    /// set, e.g. 0002 for world-writable or 4000 for setuid; directories
+    /// are still descended
+    #[clap(long, value_name = "MASK", value_parser = parse_octal)]
+    mode_mask: Option<u32>,
+
+    /// With --mode-mask, only report entries whose bits under the mask
+    /// are exactly these (octal)
+    #[clap(long, value_name = "BITS", value_parser = parse_octal, requires = "mode-mask")]
+    mode_match: Option<u32>,
+
+    /// Only report empty files, and directories with nothing in them
+    #[clap(long)]
+    empty: bool,
+
+    /// Stop after reporting N entries, noting that the limit was reached,
+    /// as a guard against scanning far more than intended
+    #[clap(long, value_name = "N")]
+    max_files: Option<u64>,
+
+    /// Print a subtotal of the bytes directly inside each directory
+    #[clap(long)]
+    dir_totals: bool,
+
+    /// Leave out the header (and subtotal) of any directory whose listing
+    /// shows nothing, as when filters leave nothing in it
+    #[clap(long)]
+    prune_empty_dirs: bool,
+
+    /// Show sizes as 1.2K, 340M, 4.1G (powers of 1024)
+    #[clap(long)]
+    human: bool,
+
+    /// Don't read file contents; the hash column is left as dashes
+    #[clap(long)]
+    no_hash: bool,
+
+    /// Never open files, only stat them: no hashing (as --no-hash) and
+    /// no looking inside archives; with --debug, list the files that
+    /// would have been hashed
+    #[clap(long, alias = "dry-run", conflicts_with = "find-dupes")]
+    stat_only: bool,
+
+    /// Leave out empty regular files (as --min-size 1 would)
+    #[clap(long)]
+    exclude_size_zero: bool,
+
+    /// Descend into other filesystems mounted below the roots (they're
+    /// still marked as mountpoints)
+    #[clap(long)]
+    cross_device: bool,
+
+    /// Mark mountpoints with their filesystem type and source, as in
+    /// `(mountpoint: ext4 on /dev/sda1)`, from /proc/self/mountinfo
+    /// (Linux only; elsewhere they're just marked)
+    #[clap(long)]
+    resolve_mountpoints: bool,
+
+    /// Descend into symlinked directories (stopping at cycles) and hash
+    /// symlinked files by their targets
+    #[clap(long)]
+    follow_symlinks: bool,
+
+    /// Descend into just the symlinked directories whose path, relative
+    /// to the root, or target, as the link has it, matches GLOB
+    /// (repeatable; stopping at cycles); other links are listed as usual
+    #[clap(long, value_name = "GLOB", conflicts_with = "follow-symlinks")]
+    follow_only: Vec<String>,
+
+    /// Show and total the space allocated on disk (like `du`) instead of
+    /// apparent sizes, revealing sparse files
+    #[clap(long)]
+    blocks: bool,
+
+    /// Count symlinks' own sizes (the length of their targets) in the
+    /// totals, which otherwise cover only file contents
+    #[clap(long)]
+    count_symlinks: bool,
+
+    /// Also describe what each symlink points at (type and size), or
+    /// mark it dangling
+    #[clap(long)]
+    deref_info: bool,
+
+    /// List the members of .tar, .tar.gz, .tgz and .zip files right after
+    /// them, as ARCHIVE!MEMBER, with their sizes and hashes
+    #[clap(long)]
+    into_archives: bool,
+
+    /// Count each hard-linked file's bytes once in the totals
+    #[clap(long)]
+    dedup_hardlinks: bool,
+
+    /// Show a directory's contents right under it, indented, instead of
+    /// under separate headers (implies --order dfs)
+    #[clap(long)]
+    tree: bool,
+
+    /// Where subdirectories' listings go: bfs lists a whole directory,
+    /// then each subdirectory in turn under its own header, with its
+    /// own subdirectories before the next; dfs puts a
+    /// subdirectory's listing right after its entry, and repeats the
+    /// parent's header when its listing carries on
+    #[clap(long, value_enum, default_value_t = Order::Bfs)]
+    order: Order,
+
+    /// Scan the roots in sorted order rather than the order given
+    #[clap(long)]
+    sort_roots: bool,
+
+    /// Show NAME in place of the root's path, in its header and in
+    /// absolute paths.  Repeat it once per root, or give it once to
+    /// number the roots NAME-1, NAME-2 and so on
+    #[clap(long, value_name = "NAME")]
+    root_label: Vec<String>,
+
+    /// Order of entries within each directory
+    #[clap(long, value_enum, default_value_t = SortKey::Name)]
+    sort: SortKey,
+
+    /// Reverse the --sort order: largest, newest or last by name first
+    #[clap(long, alias = "desc")]
+    reverse: bool,
+
+    /// Hash every file completely, however big (see --maxsumsize)
+    #[clap(long)]
+    full_hash: bool,
+
+    /// Show empty files' hash as the digest of no bytes, as md5sum and
+    /// the like do, instead of `empty` (always so with --verify)
+    #[clap(long)]
+    hash_empty: bool,
+
+    /// Hash just the first SIZE bytes of every file, however big, for a
+    /// quick fingerprint; such digests are shown after a `~`, and
+    /// aren't for --format md5sum
+    #[clap(long, value_name = "SIZE", value_parser = parse_size, conflicts_with_all = &["full-hash", "verify"])]
+    head_hash: Option<u64>,
+
+    /// Threads for hashing [default: number of CPUs]
+    #[clap(short, long, value_name = "N")]
+    jobs: Option<usize>,
+
+    /// Threads reading directory listings ahead of the walk, for slow
+    /// filesystems and wide trees; the output is the same as with 1
+    #[clap(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    threads: u32,
+
+    /// Roots scanned at once, each on its own thread; each root's output
+    /// is held until it's done, so it comes out in the order given
+    #[clap(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..),
+           conflicts_with = "max-files")]
+    root_jobs: u32,
+
+    /// Bytes per read when hashing, with K or M suffixes; larger reads
+    /// can help on network filesystems and spinning disks
+    #[clap(long, value_name = "SIZE", default_value = "64K", value_parser = parse_chunk)]
+    read_chunk: usize,
+
+    /// Memory-map files of 4M or more to hash them, rather than reading
+    /// them, where possible: faster, but a file truncated meanwhile kills
+    /// the scan with SIGBUS, so only for trees nothing is writing to
+    #[clap(long)]
+    mmap: bool,
+
+    /// Try a failed stat, directory listing or read up to N more times,
+    /// pausing longer each time, before reporting it: for flaky network
+    /// mounts (missing or forbidden paths aren't retried)
+    #[clap(long, value_name = "N", default_value_t = 0)]
+    retries: u32,
+
+    /// Read file contents, for hashing, --find-dupes and
+    /// --into-archives, at no more than RATE bytes a second (K, M, G
+    /// suffixes are powers of 1024), to spare a busy disk; stats and
+    /// listings aren't slowed
+    #[clap(long, value_name = "RATE", value_parser = parse_rate)]
+    limit_rate: Option<u64>,
+
+    /// Columns for each of the owner and group names; longer ones keep
+    /// their tail after a `~`
+    #[clap(long, value_name = "N", default_value_t = 8, value_parser = clap::value_parser!(u32).range(1..))]
+    owner_width: u32,
+
+    /// Shorten names longer than N characters in the table, putting `…`
+    /// in place of the middle and keeping the extension; the other
+    /// formats always have them whole
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u32).range(2..))]
+    max_name_len: Option<u32>,
+
+    /// Show each entry's inode number, in a column before the others and
+    /// in the structured formats
+    #[clap(long)]
+    show_inodes: bool,
+
+    /// List each entry's extended attributes on indented lines below it
+    #[clap(long)]
+    xattrs: bool,
+
+    /// Note file capabilities, e.g. `(caps: cap_net_bind_service+ep)`
+    #[clap(long)]
+    caps: bool,
+
+    /// Note setuid, setgid and sticky bits, e.g. `(setuid)`, so they
+    /// stand out from the mode column
+    #[clap(long)]
+    flag_special_bits: bool,
+
+    /// Write paths and link targets with `\` doubled, and control
+    /// characters and non-UTF-8 bytes as `\xHH`, so they can be recovered
+    /// exactly, rather than replacing bytes that aren't UTF-8
+    #[clap(long)]
+    escape_paths: bool,
+
+    /// Replace each name in the paths shown, and in link targets, with
+    /// its --hash digest cut to --hashlen, keeping sizes, modes and
+    /// content hashes; roots are still shown as given (see --root-label)
+    #[clap(long)]
+    hash_names: bool,
+
+    /// Drop the first N names from each path reported, like tar's
+    /// --strip-components, so trees under different prefixes line up;
+    /// entries no deeper than N get an empty path
+    #[clap(long, value_name = "N", alias = "path-prefix-strip", default_value_t = 0)]
+    strip_components: usize,
+
+    /// Show the numeric uid and gid rather than user and group names
+    #[clap(long)]
+    numeric_ids: bool,
+
+    /// Read all user and group names once at the start, so every id is
+    /// shown the same way throughout even if they change; ids they
+    /// don't list show as ?
+    #[clap(long, conflicts_with = "numeric-ids")]
+    snapshot_ids: bool,
+
+    /// How permissions are shown
+    #[clap(long, value_enum, default_value_t = ModeFormat::Symbolic)]
+    mode_format: ModeFormat,
+
+    /// strftime pattern for modification times
+    #[clap(long, value_name = "STRFTIME", default_value = DEFAULT_TIME_FORMAT, value_parser = check_time_format)]
+    time_format: String,
+
+    /// Show times to the second, minute or day, instead of a
+    /// --time-format; coarser times hide small changes from --baseline
+    /// [default: minute]
+    #[clap(long, value_enum, value_name = "UNIT", conflicts_with = "time-format")]
+    time_precision: Option<TimePrecision>,
+
+    /// Show times in the local timezone rather than UTC
+    #[clap(long)]
+    local_time: bool,
+
+    /// Timestamp columns to show, in order: m (modified), a (accessed),
+    /// c (changed)
+    #[clap(long, value_name = "SET", default_value = "m", value_delimiter = ',', value_parser = TimeKind::parse)]
+    times: Vec<TimeKind>,
+
+    /// Leave out the root and directory headers, and report unreadable
+    /// entries only as a count at the end (--debug output is unaffected)
+    #[clap(short, long)]
+    quiet: bool,
+
+    /// Don't print errors for paths matching GLOB, as the errors show
+    /// them, e.g. `/proc/**` (repeatable); they still count as errors
+    #[clap(long, value_name = "GLOB")]
+    quiet_errors_for: Vec<String>,
+
+    /// Finish with counts of files, directories and symlinks, files per
+    /// extension, the largest file and the total bytes
+    #[clap(long)]
+    summary: bool,
+
+    /// Print just the --summary block, and any --group-by or
+    /// --find-dupes report, without the entries
+    #[clap(long)]
+    summary_only: bool,
+
+    /// Print just each root's total bytes, then the counts of files,
+    /// directories and symlinks and the grand total.  The whole tree is
+    /// still walked, but nothing is hashed
+    #[clap(long, conflicts_with_all = &["summary-only", "verify", "null", "format"])]
+    count_only: bool,
+
+    /// Finish with a table of file counts and bytes per group, largest
+    /// first
+    #[clap(long, value_enum, value_name = "KEY")]
+    group_by: Option<GroupBy>,
+
+    /// After the scan, list sets of files with identical contents (files
+    /// sharing a size are hashed in full to check)
+    #[clap(long)]
+    find_dupes: bool,
+
+    /// After the scan, list the N largest files, biggest first (ties by
+    /// path)
+    #[clap(long, value_name = "N")]
+    top: Option<usize>,
+
+    /// Show a running count of entries and bytes hashed on stderr
+    #[clap(long)]
+    progress: bool,
+
+    /// At the end, print how long the scan took and how fast files were
+    /// hashed, on stderr
+    #[clap(long)]
+    timing: bool,
+
+    /// Stop at the first entry that can't be read, rather than
+    /// reporting it and carrying on
+    #[clap(long)]
+    strict: bool,
+
+    /// Compare with an earlier `--format json` (or ndjson) scan, marking
+    /// entries added or modified and listing removed ones at the end
+    #[clap(long, value_name = "FILE", parse(from_os_str))]
+    baseline: Option<PathBuf>,
+
+    /// With --baseline, take the baseline's digest for files whose size
+    /// and mtime (to the nanosecond) are unchanged, if it was made with
+    /// the same --hash, rather than reading them again; an edit that
+    /// keeps both the size and the mtime goes unnoticed
+    #[clap(long, requires = "baseline")]
+    reuse_digests: bool,
+
+    /// Check files against the digests in FILE, an earlier `--format
+    /// json` (or ndjson) scan or an `md5sum`-style list, printing OK,
+    /// MISMATCH or MISSING for each path it lists instead of the usual
+    /// output; files are hashed whatever their size
+    #[clap(long, value_name = "FILE", parse(from_os_str), conflicts_with_all = &["null", "no-hash", "stat-only"])]
+    verify: Option<PathBuf>,
+
+    /// Table columns to show, in order, from inode, perms, size, owner,
+    /// group, mtime, atime, ctime, hash and name [default: the usual set, with
+    /// times from --times]
+    #[clap(long, value_enum, value_name = "LIST", value_delimiter = ',')]
+    columns: Vec<Column>,
+
+    /// Write to PATH instead of stdout
+    #[clap(short, long, value_name = "PATH", parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Write each root's listing to its own file, named by TEMPLATE with
+    /// `{root}` replaced by the root's --root-label or, failing that,
+    /// its path with anything but letters, digits, `-`, `_` and `.`
+    /// made `_`; summaries and the like still go to stdout
+    #[clap(long, value_name = "TEMPLATE", conflicts_with_all = &["output", "resume"])]
+    output_per_root: Option<String>,
+
+    /// Also write each error to FILE, as a JSON object per line with
+    /// the `error` and the `path` it concerns (null if none)
+    #[clap(long, value_name = "FILE")]
+    error_output: Option<PathBuf>,
+
+    /// Save the scan's progress to FILE every few seconds and at the
+    /// end: the roots finished, and the top-level directories under a
+    /// root whose subtrees were, for --resume
+    #[clap(long, value_name = "FILE", conflicts_with = "root-jobs")]
+    checkpoint: Option<PathBuf>,
+
+    /// Carry on an interrupted scan, skipping what FILE from --checkpoint
+    /// says was finished and saving further progress there.  Output is
+    /// added to the end of --output, without a second CSV or TSV header;
+    /// formats making a single document or database can't be added to.
+    /// The unfinished root's own listing and its unfinished subtree are
+    /// reported again
+    #[clap(long, value_name = "FILE", conflicts_with = "root-jobs")]
+    resume: Option<PathBuf>,
+
+    /// Print only the paths of non-directories, each followed by a NUL
+    /// byte, for `xargs -0`
+    #[clap(short = '0', long)]
+    null: bool,
+
+    /// Keep the output to ASCII: anything else is written as `\uXXXX`, as
+    /// JSON escapes it (so JSON output reads back the same), and names
+    /// cut short by --max-name-len are marked with `~` rather than `…`
+    #[clap(long, conflicts_with = "null")]
+    ascii_only: bool,
+
+    /// Output format
+    #[clap(long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+
+    /// Start --format tsv output with a row of column names
+    #[clap(long)]
+    header: bool,
+
+    /// How entries' paths are shown [default: name for the table,
+    /// relative for the other formats]
+    #[clap(long, value_enum)]
+    path_style: Option<PathStyle>,
+
+    /// Color names in the table by kind, like `ls --color`
+    #[clap(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Also scan each path listed in FILE, one per line (`-` reads
+    /// stdin); blank lines are skipped
+    #[clap(long, value_name = "FILE", parse(from_os_str))]
+    paths_from: Option<PathBuf>,
+
+    /// Paths in --paths-from end with NUL bytes rather than newlines,
+    /// as from `find -print0`
+    #[clap(long, requires = "paths-from")]
+    from0: bool,
+
     /// Files to process
     #[clap(name = "PATHS", parse(from_os_str))]
     paths: Vec<PathBuf>,
 }
 
 
-struct Scanner<'a> {
-    args: &'a Args,
-    users: HashMap::<u32, String>,
-    groups: HashMap::<u32, String>,
-    root: PathBuf,
-    parent: PathBuf,
-    dev: u64,
-    count: u64,
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    /// Fixed-width columns with per-directory headers
+    Table,
+    /// A single JSON array of entries
+    Json,
+    /// One JSON object per line
+    Ndjson,
+    /// One JSON object per line for each entry ("file"), and for each
+    /// directory as its listing begins ("enter") and ends ("leave"),
+    /// nested as the tree is; always lists depth first
+    JsonStream,
+    /// An SQLite database, written to --output
+    Sqlite,
+    /// A Parquet file, written to --output, with sizes, ids and times
+    /// as typed columns
+    Parquet,
+    /// A header row, then one row per entry
+    Csv,
+    /// CSV's columns, tab-separated and unquoted, with `\t`, `\n`, `\r`
+    /// and `\\` escapes
+    Tsv,
+    /// Like `git ls-tree -r -t`: Git's mode, type, the hash column and
+    /// the path; the hashes are treescan's, not Git object ids
+    GitTree,
+    /// Like `ls -l`: mode, links, owner, group, size, date and path,
+    /// with `-> TARGET` after symlinks
+    Ls,
+    /// `HASH  PATH` lines for `md5sum -c` (or sha256sum and the rest,
+    /// following --hash): every file hashed in full, other entries left out
+    Md5sum,
+    /// A self-contained HTML page: the totals, then a table of entries
+    /// that sorts by a column when its heading is clicked
+    Html,
+    /// A YAML sequence of entries
+    Yaml,
+    /// An `[[entry]]` table per entry
+    Toml,
 }
 
 
-impl<'a> Scanner<'a> {
-    fn new(args: &'a Args) -> Self {
-        Self {
-            args: args,
-            users: HashMap::new(),
-            groups: HashMap::new(),
-            root: PathBuf::new(),
-            parent: PathBuf::new(),
-            dev: 0,
-            count: 0,
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum GroupBy {
+    /// The file extension (after the last dot), or `(none)`
+    Ext,
+}
+
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum PathStyle {
+    /// Just the entry's own name
+    Name,
+    /// The path from the root
+    Relative,
+    /// The full path, from the current directory if the root was relative
+    Absolute,
+}
+
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum TimePrecision {
+    Second,
+    Minute,
+    Day,
+}
+
+
+impl TimePrecision {
+    fn pattern(self) -> &'static str {
+        match self {
+            TimePrecision::Second => "%Y-%m-%dT%H:%M:%S",
+            TimePrecision::Minute => DEFAULT_TIME_FORMAT,
+            TimePrecision::Day => "%Y-%m-%d",
         }
     }
+}
 
 
-    fn scan(&mut self, depth: u32, dirs: Vec<PathBuf>) {
-        for dir in dirs {
-            if self.args.debug {
-                eprintln!("{:?}", dir.metadata());
-            }
+/// The strftime pattern for times, from --time-precision or --time-format.
+fn time_format(args: &Args) -> &str {
+    match args.time_precision {
+        Some(precision) => precision.pattern(),
+        None => &args.time_format,
+    }
+}
 
-            if depth == 0 {
-                self.root = dir.to_path_buf();
-                self.dev = dir.metadata().unwrap().dev();
-                self.count = 0;
 
-                println!("{}", "-".repeat(40));
-                println!("(root) {}:", dir.to_string_lossy());
-            }
-            else {
-                println!();
-                self.parent = dir.clone();
-                if let Ok(x) = dir.strip_prefix(&self.root) {
-                    println!("{}/:", x.to_string_lossy());
-                }
-            }
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Column {
+    Inode,
+    Perms,
+    Size,
+    Owner,
+    Group,
+    Mtime,
+    Atime,
+    Ctime,
+    Hash,
+    Name,
+}
 
-            self.visit(depth, WalkDir::new(dir)
-                .sort_by_file_name()
-                .min_depth(1)
-                .max_depth(1)
-                .same_file_system(true)
-            );
 
-            if depth == 0 {
-                println!("total bytes: {}", self.count);
-            }
+impl Column {
+    fn time(kind: TimeKind) -> Column {
+        match kind {
+            TimeKind::Modified => Column::Mtime,
+            TimeKind::Accessed => Column::Atime,
+            TimeKind::Changed => Column::Ctime,
         }
     }
+}
 
 
-    fn visit(&mut self, depth: u32, walk: WalkDir) {
-        let mut dirs: Vec<PathBuf> = Vec::new();
+/// The table's columns: as given, or else the traditional layout.
+fn table_columns(args: &Args) -> Vec<Column> {
+    if !args.columns.is_empty() {
+        return args.columns.clone();
+    }
 
-        for res in walk {
-            if self.args.debug {
-                eprintln!("visit {:?}", res);
-            }
+    let mut columns = Vec::new();
+    if args.show_inodes {
+        columns.push(Column::Inode);
+    }
+    columns.extend([Column::Perms, Column::Size, Column::Owner, Column::Group]);
+    columns.extend(args.times.iter().map(|kind| Column::time(*kind)));
+    columns.extend([Column::Hash, Column::Name]);
+    columns
+}
 
-            if let Ok(entry) = res {
-                let path = entry.path();
-                let buf = path.to_path_buf();
 
-                self.report(&buf);
+/// The timestamps the scan has to gather for the chosen columns.
+fn wanted_times(args: &Args) -> Vec<TimeKind> {
+    if args.columns.is_empty() {
+        return args.times.clone();
+    }
 
-                if path.is_dir() && !path.is_symlink() {
-                    if path.metadata().unwrap().dev() == self.dev {
-                        dirs.push(buf);
-                    }
-                }
+    [TimeKind::Modified, TimeKind::Accessed, TimeKind::Changed].into_iter()
+        .filter(|kind| args.columns.contains(&Column::time(*kind)))
+        .collect()
+}
+
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Order {
+    Bfs,
+    Dfs,
+}
+
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ColorChoice {
+    /// Only when writing to a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+
+const ROW_COLUMNS: [&str; 10] = ["path", "size", "mode", "owner", "group", "mtime", "hash", "kind", "target", "error"];
+
+
+/// Shorten a user or group name to fit a `width`-column field, keeping
+/// the tail after a `~`.
+fn clip_name(name: &str, width: usize) -> String {
+    let len = name.chars().count();
+    if len > width {
+        let tail: String = name.chars().skip(len + 1 - width).collect();
+        format!("~{}", tail)
+    }
+    else {
+        name.into()
+    }
+}
+
+
+/// Shorten a name to `max` characters for the table by putting `mark`
+/// (`…`, usually) in place of its middle, keeping its extension whole
+/// where that leaves room for some of the rest.
+fn elide_name(name: &str, max: usize, mark: char) -> Cow<'_, str> {
+    let chars: Vec<char> = name.chars().collect();
+    if chars.len() <= max {
+        return Cow::Borrowed(name);
+    }
+
+    let room = max.saturating_sub(1);
+    let ext = match name.rfind('.') {
+        Some(dot) if dot > 0 => name[dot..].chars().count(),
+        _ => 0,
+    };
+    // the tail is half the room, or the extension if that's longer
+    let tail = if ext < room { ext.max(room / 2) } else { room / 2 };
+    let head = room - tail;
+    let mut out: String = chars[..head].iter().collect();
+    out.push(mark);
+    out.extend(&chars[chars.len() - tail..]);
+    Cow::Owned(out)
+}
+
+
+/// The `ls` color for an entry's name, if its kind gets one.
+fn kind_color(entry: &ScanEntry) -> Option<&'static str> {
+    match entry.kind {
+        Kind::Dir => Some("\x1b[01;34m"),
+        Kind::Symlink => Some("\x1b[01;36m"),
+        Kind::Special => match entry.mode_bits.map(|bits| bits & 0o170000) {
+            Some(0o010000) => Some("\x1b[33m"),
+            Some(0o140000) => Some("\x1b[01;35m"),
+            Some(0o020000) | Some(0o060000) => Some("\x1b[01;33m"),
+            _ => None,
+        },
+        Kind::File => None,
+    }
+}
+
+
+/// Quote a CSV field per RFC 4180, if it needs it.
+fn csv_field(text: &str) -> Cow<'_, str> {
+    if text.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", text.replace('"', "\"\"")))
+    }
+    else {
+        Cow::Borrowed(text)
+    }
+}
+
+
+/// Escape a TSV field so it holds no tabs or line breaks, and so the
+/// escapes can be undone: a backslash doubles.
+fn tsv_field(text: &str) -> Cow<'_, str> {
+    if !text.contains(['\\', '\t', '\n', '\r']) {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len() + 8);
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    Cow::Owned(out)
+}
+
+
+/// An entry as `git ls-tree` shows one: `100644 blob HASH\tPATH`.  Git
+/// has no devices or FIFOs, so those have no line.
+fn git_tree_line(entry: &ScanEntry) -> Option<String> {
+    let (mode, kind) = match entry.kind {
+        Kind::Dir => ("040000", "tree"),
+        Kind::Symlink => ("120000", "blob"),
+        Kind::File if entry.mode_bits.is_some_and(|bits| bits & 0o111 != 0) => ("100755", "blob"),
+        Kind::File => ("100644", "blob"),
+        Kind::Special => return None,
+    };
+    let hash = if entry.hash.is_empty() { "-" } else { &entry.hash };
+    Some(format!("{} {} {}\t{}", mode, kind, hash, git_path(&entry.path)))
+}
+
+
+/// An entry as `ls -l` shows one.  As there, the date has the time of
+/// day if it's within six months of `now`, and the year otherwise.
+/// Columns are fixed-width rather than fitted to each listing.
+fn ls_line(entry: &ScanEntry, owner_width: usize, local: bool, now: i64) -> String {
+    use chrono::{DateTime, Local};
+
+    let mode = entry.mode_bits.map_or_else(|| "??????????".into(), unix_mode::to_string);
+    let nlink = entry.nlink.map_or_else(|| "?".into(), |count| count.to_string());
+    let date = match entry.mtime_unix.and_then(|secs| DateTime::from_timestamp(secs, 0)) {
+        Some(time) => {
+            const HALF_YEAR: i64 = 365 * 24 * 60 * 60 / 2;
+            let recent = (now - HALF_YEAR..=now).contains(&time.timestamp());
+            let pattern = if recent { "%b %e %H:%M" } else { "%b %e  %Y" };
+            if local {
+                time.with_timezone(&Local).format(pattern).to_string()
             }
             else {
-                println!("err {:?}", res);
+                time.format(pattern).to_string()
             }
         }
+        // directories carry no time
+        None => " ".repeat(12),
+    };
 
-        self.scan(depth + 1, dirs);
+    let mut line = format!("{} {:>3} {:<ow$} {:<ow$} {:>8} {} {}", mode, nlink, entry.owner, entry.group,
+        entry.size, date, entry.path, ow = owner_width);
+    if let Some(target) = &entry.target {
+        line.push_str(" -> ");
+        line.push_str(target);
     }
+    line
+}
 
 
-    fn report(&mut self, path: &PathBuf) {
-        let mut perms = String::new();
-        let mut flen = 0;
-        let mut owner = String::new();
-        let mut ts = String::new();
-        let mut hash = String::new();
-        let mut extra = String::new();
+/// A path quoted as Git does when it has to be: in double quotes, with C
+/// escapes for quotes, backslashes and control characters.
+fn git_path(path: &str) -> Cow<'_, str> {
+    if !path.contains(|c: char| c == '"' || c == '\\' || c.is_ascii_control()) {
+        return Cow::Borrowed(path);
+    }
+    let mut out = String::from("\"");
+    for c in path.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_ascii_control() => out.push_str(&format!("\\{:03o}", c as u8)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    Cow::Owned(out)
+}
+
+
+/// The start of a --format html page, up to the table's first row.
+const HTML_HEAD: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>treescan</title>
+<style>
+body { font-family: sans-serif; display: flex; flex-direction: column; }
+/* written after the table, once the totals are known, but shown first */
+#summary { order: -1; }
+table { border-collapse: collapse; font-size: 90%; }
+th { cursor: pointer; text-align: left; background: #e8e8e8; }
+th, td { padding: 2px 8px; }
+td.num { text-align: right; }
+td.hash { font-family: monospace; }
+tbody tr:nth-child(even) { background: #f6f6f6; }
+</style>
+</head>
+<body>
+<table>
+<thead><tr><th>Path</th><th>Kind</th><th>Size</th><th>Mode</th><th>Owner</th><th>Group</th><th>Modified</th><th>Hash</th><th>Notes</th></tr></thead>
+<tbody>
+"#;
+
+
+/// The end of a --format html table.
+const HTML_TABLE_END: &str = "</tbody>\n</table>\n";
 
-        let fname = match path.file_name() {
-            Some(name) => name.to_string_lossy(),
-            None => Cow::Borrowed("?"),
-        };
 
-        let meta = if path.is_symlink() {
-            std::fs::symlink_metadata(path)
+/// The end of a --format html page, with the script that sorts the table.
+const HTML_TAIL: &str = r#"<script>
+document.querySelectorAll("th").forEach((th, col) => th.addEventListener("click", () => {
+  const body = th.closest("table").tBodies[0];
+  const key = td => td.dataset.sort !== undefined ? Number(td.dataset.sort) : td.textContent;
+  const up = th.dataset.order !== "up";
+  th.dataset.order = up ? "up" : "down";
+  const rows = Array.from(body.rows).sort((a, b) => {
+    const x = key(a.cells[col]), y = key(b.cells[col]);
+    return (x < y ? -1 : x > y ? 1 : 0) * (up ? 1 : -1);
+  });
+  rows.forEach(row => body.appendChild(row));
+}));
+</script>
+</body>
+</html>
+"#;
+
+
+/// One entry as a row of the --format html table.
+fn html_row(args: &Args, entry: &ScanEntry) -> String {
+    let mut notes = Vec::new();
+    if let Some(target) = &entry.target {
+        notes.push(format!("-> {}", target));
+    }
+    notes.extend(entry.notes.iter().cloned());
+    if let Some(error) = &entry.error {
+        notes.push(format!("error: {}", error));
+    }
+    if let Some(change) = entry.change.filter(|change| *change != Change::Unchanged) {
+        notes.push(format!("[{}]", change.as_str()));
+    }
+
+    format!("<tr><td>{}</td><td>{}</td><td class=\"num\" data-sort=\"{}\">{}</td><td>{}</td><td>{}</td><td>{}</td>\
+             <td>{}</td><td class=\"hash\">{}</td><td>{}</td></tr>\n",
+        html_escape(&entry.path), entry.kind.as_str(), entry.size, size_str(args, entry.size),
+        html_escape(entry.mode.as_deref().unwrap_or("?")), html_escape(&entry.owner), html_escape(&entry.group),
+        html_escape(&entry.mtime), html_escape(&entry.hash), html_escape(&notes.join(" ")))
+}
+
+
+/// The totals heading a --format html page.
+fn write_html_summary(out: &mut impl Write, args: &Args, summary: &Summary) -> io::Result<()> {
+    writeln!(out, "<section id=\"summary\">")?;
+    writeln!(out, "<h1>treescan</h1>")?;
+    writeln!(out, "<p>{} files, {} directories, {} symlinks, {} other; total {}: {}</p>", summary.files,
+        summary.dirs, summary.symlinks, summary.others, bytes_label(args), size_str(args, summary.bytes))?;
+    if let Some((path, size)) = &summary.largest {
+        writeln!(out, "<p>largest: {} ({})</p>", html_escape(&path_str(args, path)), size_str(args, *size))?;
+    }
+    writeln!(out, "</section>")
+}
+
+
+/// `text` with the characters HTML gives meaning to replaced by entities.
+fn html_escape(text: &str) -> Cow<'_, str> {
+    if !text.contains(['<', '>', '&', '"', '\'']) {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len() + 16);
+    for c in text.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
         }
-        else {
-            path.metadata()
-        };
+    }
+    Cow::Owned(out)
+}
 
-        let otherdev;
-        if let Ok(meta) = meta {
-            flen = meta.len();
-            perms.push_str(&unix_mode::to_string(meta.permissions().mode()));
-            otherdev = meta.dev() != self.dev;
-            if let Ok(mtime) = meta.modified() {
-                let now: DateTime<Utc> = mtime.into();
-                ts.push_str(&format!("{}", now.format("%Y-%m-%dT%H:%M")));
-            }
-            else {
-                ts.push_str("?");
-            }
-
-            let uid = meta.uid();
-            let user: &str = match self.users.get(&uid) {
-                Some(name) => &name,
-                None => {
-                    let name = match users::get_user_by_uid(uid) {
-                        Some(user) => user.name().to_string_lossy().into_owned(),
-                        None => "?".into(),
-                    };
-                    self.users.insert(uid, name.into());
-                    self.users.get(&uid).unwrap()
-                }
-            };
 
-            let user: String = if user.len() > 8 {
-                format!("~{:<7}", &user[user.len()-7..])
-            }
-            else {
-                user.into()
-            };
+/// One entry as an item of a YAML block sequence.
+fn yaml_item(entry: &ScanEntry) -> io::Result<String> {
+    let text = serde_yaml::to_string(entry).map_err(io::Error::other)?;
+    let mut item = String::with_capacity(text.len() + 64);
+    for (i, line) in text.lines().enumerate() {
+        item.push_str(if i == 0 { "- " } else { "  " });
+        item.push_str(line);
+        item.push('\n');
+    }
+    Ok(item)
+}
 
 
-            let gid = meta.gid();
-            let group: &str = match self.groups.get(&gid) {
-                Some(name) => name,
-                None => {
-                    let name = match users::get_group_by_gid(gid) {
-                        Some(grp) => grp.name().to_string_lossy().into_owned(),
-                        None => "?".into(),
-                    };
-                    self.groups.insert(gid, name.into());
-                    self.groups.get(&gid).unwrap()
-                }
-            };
+/// One entry as a TOML `[[entry]]` table, TOML having no top-level
+/// arrays; fields that are null elsewhere are left out.
+fn toml_item(entry: &ScanEntry) -> io::Result<String> {
+    #[derive(Serialize)]
+    struct Doc<'e> {
+        entry: [&'e ScanEntry; 1],
+    }
+    toml::to_string(&Doc { entry: [entry] }).map_err(io::Error::other)
+}
 
-            let group: String = if group.len() > 8 {
-                format!("~{:<7}", &group[group.len()-7..])
-            }
-            else {
-                group.into()
-            };
 
-            owner.push_str(&format!("{:8} {:8}", &user, &group));
+/// Split a list of paths on newlines (dropping any `\r`) or NULs,
+/// skipping empty ones.
+fn read_paths(mut input: impl BufRead, from0: bool) -> io::Result<Vec<PathBuf>> {
+    let delim = if from0 { b'\0' } else { b'\n' };
+    let mut paths = Vec::new();
+    let mut item = Vec::new();
+
+    while input.read_until(delim, &mut item)? > 0 {
+        if item.last() == Some(&delim) {
+            item.pop();
         }
-        else {
-            perms.push_str("no meta");
-            otherdev = false;
-        }
-
-        if path.is_symlink() {
-            extra.push_str(" -> ");
-            extra.push_str(&std::fs::read_link(path).unwrap().to_string_lossy());
-            self.count += flen;
-        }
-        else if path.is_dir() {
-            extra.push_str("/");
-            ts.clear();
-            hash.clear();
-            flen = 0;
-            if otherdev {
-                extra.push_str(" (mountpoint)");
-            }
-        }
-        else if path.is_file() {
-            self.count += flen;
-
-            if flen > 0 && flen < self.args.maxsumsize * 1024*1024 {
-                let mut md5 = Context::new();
-                if let Ok(mut file) = std::fs::File::open(&path) {
-                    // println!("reading {}, len {}", path.to_string_lossy(), flen);
-                    const CHUNK: usize = 1024*64;
-                    let mut chunk = Vec::with_capacity(CHUNK);
-                    while let Ok(n) = file.by_ref().take(CHUNK as u64).read_to_end(&mut chunk) {
-                        // let mut hash = Context::new();
-                        // hash.consume(&chunk[..n]);
-                        // println!("read {} {}", n, hex::encode(hash.compute().0));
-                        md5.consume(&chunk[..n]);
-                        if n < CHUNK { break; }
-                        chunk.clear();
-                    }
-                }
-                hash.push_str(&hex::encode(md5.compute().0)[..8]);
-            }
-            else {
-                hash.push_str(&format!("{}", "-".repeat(self.args.hashlen as usize)));
-            }
+        if !from0 && item.last() == Some(&b'\r') {
+            item.pop();
         }
-        else {
-            // extra.push_str(" (special)");
+        if !item.is_empty() {
+            paths.push(platform::path_from_bytes(std::mem::take(&mut item)));
+        }
+        item.clear();
+    }
+    Ok(paths)
+}
+
+
+/// Reject strftime patterns chrono can't render.
+fn check_time_format(text: &str) -> Result<String, String> {
+    use chrono::format::{Item, StrftimeItems};
+
+    if StrftimeItems::new(text).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("invalid time format {:?}", text));
+    }
+    Ok(text.into())
+}
+
+
+/// Parse a byte count such as `512`, `64K` or `10M` (powers of 1024).
+fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let (digits, shift) = match text.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => {
+            let shift = match c.to_ascii_uppercase() {
+                'K' => 10,
+                'M' => 20,
+                'G' => 30,
+                'T' => 40,
+                _ => return Err(format!("unknown size suffix {:?}", c)),
+            };
+            (&text[..i], shift)
         }
+        _ => (text, 0),
+    };
+
+    let n: u64 = digits.parse().map_err(|_| format!("invalid size {:?}", text))?;
+    n.checked_mul(1 << shift).ok_or_else(|| format!("size {:?} is too large", text))
+}
+
 
-        println!("{:10} {:10} {:17} {:16} {:8} {}{}", perms, flen, owner, ts, hash, fname, extra);
+/// Parse permission bits given in octal, such as `0002` or `4000`.
+fn parse_octal(text: &str) -> Result<u32, String> {
+    let text = text.trim();
+    match u32::from_str_radix(text, 8) {
+        Ok(bits) if bits <= 0o7777 => Ok(bits),
+        _ => Err(format!("invalid permission bits {:?}, expected octal up to 7777", text)),
     }
+}
+
 
+/// A read size for --read-chunk: as for parse_size(), but at least a
+/// byte and small enough to allocate.
+fn parse_chunk(text: &str) -> Result<usize, String> {
+    match parse_size(text)? {
+        0 => Err("read size must be at least 1 byte".into()),
+        n if n > 1 << 30 => Err(format!("read size {:?} is over 1G", text)),
+        n => Ok(n as usize),
+    }
 }
 
 
-fn main() {
-    let args = Args::from_args();
+/// A read rate for --limit-rate: as for parse_size(), but at least a
+/// byte a second.
+fn parse_rate(text: &str) -> Result<u64, String> {
+    match parse_size(text)? {
+        0 => Err("rate must be at least 1 byte a second".into()),
+        n => Ok(n),
+    }
+}
 
-    let mut paths = args.paths.clone();
-    if paths.len() == 0 {
-        paths.push(".".into());
+
+/// Parse a point in time: RFC 3339 (`2022-07-01T12:00:00+02:00`), a
+/// date and time taken as UTC (`2022-07-01T12:00`, seconds optional, a
+/// space for the `T` allowed), a date for its midnight UTC, or an age
+/// like `90m` or `2d`.
+fn parse_when(text: &str) -> Result<SystemTime, String> {
+    when_at(text, SystemTime::now())
+}
+
+
+/// `parse_when()`, with ages counted back from `now`.
+fn when_at(text: &str, now: SystemTime) -> Result<SystemTime, String> {
+    use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime};
+
+    let text = text.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.into());
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(time) = NaiveDateTime::parse_from_str(text, format) {
+            return Ok(time.and_utc().into());
+        }
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc().into());
     }
 
-    let mut scanner = Scanner::new(&args);
-    scanner.scan(0, paths);
+    let (digits, unit) = match text.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&text[..i], c.to_ascii_lowercase()),
+        _ => return Err(format!("invalid time {:?}", text)),
+    };
+    let scale = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return Err(format!("unknown age unit {:?}", unit)),
+    };
+    let n: u64 = digits.parse().map_err(|_| format!("invalid time {:?}", text))?;
+    n.checked_mul(scale)
+        .and_then(|secs| now.checked_sub(Duration::from_secs(secs)))
+        .ok_or_else(|| format!("age {:?} is too large", text))
 }
 
 
-#[cfg(test)]
-mod test {
-    use super::*;
+/// Binary-unit size like `ls -h`: one decimal below 10, none above.
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 7] = ["", "K", "M", "G", "T", "P", "E"];
+
+    if bytes < 1024 {
+        return bytes.to_string();
+    }
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    // compare the rounded value so 1048575 becomes 1.0M, not 1024K
+    while unit < UNITS.len() - 1 && value >= 1023.5 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if value < 9.95 {
+        format!("{:.1}{}", value, UNITS[unit])
+    }
+    else {
+        format!("{:.0}{}", value, UNITS[unit])
+    }
+}
+
+
+fn write_summary(out: &mut impl Write, args: &Args, summary: &Summary) -> io::Result<()> {
+    writeln!(out, "{}", "-".repeat(40))?;
+    writeln!(out, "files: {}", summary.files)?;
+    writeln!(out, "directories: {}", summary.dirs)?;
+    writeln!(out, "symlinks: {}", summary.symlinks)?;
+    writeln!(out, "other: {}", summary.others)?;
+    if args.empty {
+        writeln!(out, "empty: {}", summary.empty)?;
+    }
+    writeln!(out, "total {}: {}", bytes_label(args), size_str(args, summary.bytes))?;
+    if let Some((path, size)) = &summary.largest {
+        writeln!(out, "largest: {} ({})", path_str(args, path), size_str(args, *size))?;
+    }
+
+    // most common first
+    let mut exts: Vec<(&String, &u64)> = summary.extensions.iter().collect();
+    exts.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    if !exts.is_empty() {
+        writeln!(out, "extensions:")?;
+    }
+    for (ext, count) in exts {
+        let ext = if ext.is_empty() { "(none)".into() } else { format!(".{}", ext) };
+        writeln!(out, "  {:10} {}", ext, count)?;
+    }
+    Ok(())
+}
+
+
+/// The --group-by ext table: extensions by total size, biggest first.
+fn write_groups(out: &mut impl Write, args: &Args, summary: &Summary) -> io::Result<()> {
+    let mut exts: Vec<(&String, &u64)> = summary.extension_bytes.iter().collect();
+    exts.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+
+    writeln!(out, "{}", "-".repeat(40))?;
+    writeln!(out, "by extension:")?;
+    for (ext, bytes) in exts {
+        let files = summary.extensions.get(ext).copied().unwrap_or(0);
+        let ext = if ext.is_empty() { "(none)".into() } else { format!(".{}", ext) };
+        writeln!(out, "  {:10} {:>8} files {:>12}", ext, files, size_str(args, *bytes))?;
+    }
+    Ok(())
+}
+
+
+fn write_dupes(out: &mut impl Write, args: &Args, groups: &[DupeGroup]) -> io::Result<()> {
+    writeln!(out, "{}", "-".repeat(40))?;
+    writeln!(out, "duplicates: {} sets, {} wasted", groups.len(),
+        size_str(args, groups.iter().map(DupeGroup::wasted).sum()))?;
+    for group in groups {
+        writeln!(out)?;
+        writeln!(out, "{} files of {}, {} wasted:", group.paths.len(),
+            size_str(args, group.size), size_str(args, group.wasted()))?;
+        for path in &group.paths {
+            writeln!(out, "  {}", path_str(args, path))?;
+        }
+    }
+    Ok(())
+}
+
+
+/// The --top list.
+fn write_top(out: &mut impl Write, args: &Args, files: &[(PathBuf, u64)]) -> io::Result<()> {
+    writeln!(out, "{}", "-".repeat(40))?;
+    writeln!(out, "largest files:")?;
+    for (path, size) in files {
+        writeln!(out, "  {:>12} {}", size_str(args, *size), path_str(args, path))?;
+    }
+    Ok(())
+}
+
+
+/// A path for display, escaped like the entries with --escape-paths.
+fn path_str(args: &Args, path: &Path) -> String {
+    if args.escape_paths {
+        treescan::escape_bytes(&platform::path_bytes(path))
+    }
+    else {
+        path.to_string_lossy().into_owned()
+    }
+}
+
+
+/// Each root's --root-label, by path: one per root in the order given,
+/// or numbered from a single one.
+fn root_labels(args: &Args, roots: &[PathBuf]) -> Result<HashMap<PathBuf, String>, String> {
+    let labels = &args.root_label;
+    if labels.is_empty() {
+        Ok(HashMap::new())
+    }
+    else if labels.len() == roots.len() {
+        Ok(roots.iter().cloned().zip(labels.iter().cloned()).collect())
+    }
+    else if labels.len() == 1 {
+        Ok(roots.iter().enumerate()
+            .map(|(i, root)| (root.clone(), format!("{}-{}", labels[0], i + 1)))
+            .collect())
+    }
+    else {
+        Err(format!("{} root labels for {} roots", labels.len(), roots.len()))
+    }
+}
+
+
+/// `entry` with its path shown as --path-style says: the table shows
+/// `name`, everything else `path`.  Entries from a baseline have no
+/// filesystem path, and hashed names would give the real ones away, so
+/// their absolute form stays relative; a labelled root's stand in for it.
+fn styled<'e>(args: &Args, entry: &'e ScanEntry, label: Option<&str>) -> Cow<'e, ScanEntry> {
+    let table = args.format == Format::Table;
+    let style = args.path_style.unwrap_or(if table { PathStyle::Name } else { PathStyle::Relative });
+    let text = match style {
+        PathStyle::Name if table => return Cow::Borrowed(entry),
+        PathStyle::Relative if !table => return Cow::Borrowed(entry),
+        PathStyle::Name => entry.name.clone(),
+        PathStyle::Relative => entry.path.clone(),
+        PathStyle::Absolute => match label {
+            Some(label) => format!("{}/{}", label, entry.path),
+            None if entry.fs_path.as_os_str().is_empty() || args.hash_names => entry.path.clone(),
+            None => {
+                let full = std::path::absolute(&entry.fs_path).unwrap_or_else(|_| entry.fs_path.clone());
+                path_str(args, &full)
+            }
+        },
+    };
+
+    let mut entry = entry.clone();
+    if table {
+        entry.name = text;
+    }
+    else {
+        entry.path = text;
+    }
+    Cow::Owned(entry)
+}
+
+
+/// What the totals add up, so --blocks output can't pass for the usual.
+fn bytes_label(args: &Args) -> &'static str {
+    if args.blocks { "allocated bytes" } else { "bytes" }
+}
+
+
+fn size_str(args: &Args, bytes: u64) -> String {
+    if args.human {
+        human_size(bytes)
+    }
+    else {
+        bytes.to_string()
+    }
+}
+
+
+/// Renders scan events in the selected format.
+struct Output<'a> {
+    args: &'a Args,
+    out: BufWriter<Box<dyn Write>>,
+    emitted: u64,
+    /// Width of the time column, from the format's rendered length.
+    time_width: usize,
+    color: bool,
+    columns: Vec<Column>,
+    #[cfg(feature = "sqlite")]
+    db: Option<treescan::sqlite::Writer>,
+    #[cfg(feature = "parquet")]
+    parquet: Option<treescan::parquet::Writer>,
+    /// The root being listed, for repeating its header.
+    root: PathBuf,
+    /// Where --error-output records go.
+    error_out: Option<BufWriter<Box<dyn Write>>>,
+    /// --root-label's names for the roots, and the current one's.
+    labels: HashMap<PathBuf, String>,
+    /// With --output-per-root, the files written so far.
+    root_files: HashSet<PathBuf>,
+    label: Option<String>,
+    /// A header to repeat before anything more is printed under it.
+    resume: Option<PathBuf>,
+    /// --quiet-errors-for's patterns.
+    quiet_errors: GlobSet,
+    /// With --prune-empty-dirs, whether that's a listing's own header,
+    /// left out altogether if the listing ends first.
+    pruned: bool,
+    /// For --format json-stream, the directories entered and not yet left.
+    open: Vec<String>,
+    /// For --format html, the totals, written after the table once known.
+    totals: String,
+    /// With --resume, whether --output already had rows from the
+    /// interrupted run, so CSV and TSV don't need their header again.
+    continued: bool,
+}
+
+
+/// A --format json-stream record for an entry.
+#[derive(Serialize)]
+struct StreamEntry<'e> {
+    event: &'static str,
+    #[serde(flatten)]
+    entry: &'e ScanEntry,
+}
+
+
+/// A --format json-stream record for a directory entered or left.
+#[derive(Serialize)]
+struct StreamDir {
+    event: &'static str,
+    path: String,
+    /// The root as given, or its label, entering a root.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    root: Option<String>,
+    /// Bytes directly in the listing, leaving it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes: Option<u64>,
+}
+
+
+impl<'a> Output<'a> {
+    fn new(args: &'a Args, labels: HashMap<PathBuf, String>) -> io::Result<Self> {
+        // formats written straight to their own file rather than through `out`
+        let file_format = match args.format {
+            Format::Sqlite => Some("sqlite"),
+            Format::Parquet => Some("parquet"),
+            _ => None,
+        }.filter(|_| !args.null && !args.summary_only);
+        let sqlite = file_format == Some("sqlite");
+        let parquet = file_format == Some("parquet");
+        if let (Some(name), Some(_)) = (file_format, &args.output_per_root) {
+            return Err(io::Error::other(format!("--format {} can't be split with --output-per-root", name)));
+        }
+        if args.output_per_root.as_ref().is_some_and(|template| !template.contains("{root}")) {
+            return Err(io::Error::other("--output-per-root needs {root} in its template"));
+        }
+        if let (Some(name), None) = (file_format, &args.output) {
+            return Err(io::Error::other(format!("--format {} needs --output", name)));
+        }
+        // a second document after the first, or the file started afresh,
+        // would spoil what the interrupted run wrote
+        let whole = matches!(args.format, Format::Json | Format::Yaml | Format::Toml | Format::Sqlite | Format::Parquet);
+        if whole && args.resume.is_some() && args.output.is_some() && !args.null && !args.summary_only {
+            let name = args.format.to_possible_value().map_or("", |value| value.get_name());
+            return Err(io::Error::other(format!("--format {} can't carry on an interrupted --output", name)));
+        }
+        #[cfg(not(feature = "sqlite"))]
+        if sqlite {
+            return Err(io::Error::other("built without sqlite support"));
+        }
+        #[cfg(not(feature = "parquet"))]
+        if parquet {
+            return Err(io::Error::other("built without parquet support"));
+        }
+        if args.format == Format::Md5sum && args.head_hash.is_some() && !args.null && !args.summary_only {
+            return Err(io::Error::other("--format md5sum lists whole digests, so can't be used with --head-hash"));
+        }
+        let html = args.format == Format::Html && !args.null && !args.summary_only;
+        if html && (args.output_per_root.is_some() || args.resume.is_some()) {
+            return Err(io::Error::other("--format html makes one page, so can't be split or resumed"));
+        }
+
+        let continued = match &args.output {
+            Some(path) if args.resume.is_some() => std::fs::metadata(path).is_ok_and(|meta| meta.len() > 0),
+            _ => false,
+        };
+        let mut out: Box<dyn Write> = match &args.output {
+            // the database has the file, and anything else goes to stdout
+            Some(_) if file_format.is_some() => Box::new(io::stdout()),
+            // carrying on from where an interrupted scan's output ends
+            Some(path) if args.resume.is_some() => {
+                Box::new(File::options().append(true).create(true).open(path)?)
+            }
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(io::stdout()),
+        };
+        if args.ascii_only {
+            out = Box::new(AsciiOnly::new(out));
+        }
+        let color = match args.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => args.output.is_none() && io::stdout().is_terminal(),
+        };
+
+        let sample = chrono::DateTime::<chrono::Utc>::from(std::time::UNIX_EPOCH);
+
+        let mut output = Self {
+            args,
+            out: BufWriter::with_capacity(64*1024, out),
+            emitted: 0,
+            time_width: sample.format(time_format(args)).to_string().len(),
+            color,
+            columns: table_columns(args),
+            root: PathBuf::new(),
+            error_out: match &args.error_output {
+                Some(path) => {
+                    let file: Box<dyn Write> = Box::new(File::create(path)
+                        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?);
+                    Some(BufWriter::new(if args.ascii_only { Box::new(AsciiOnly::new(file)) } else { file }))
+                }
+                None => None,
+            },
+            labels,
+            root_files: HashSet::new(),
+            label: None,
+            resume: None,
+            pruned: false,
+            quiet_errors: quiet_globs(args)?,
+            open: Vec::new(),
+            totals: String::new(),
+            continued,
+            #[cfg(feature = "sqlite")]
+            db: match &args.output {
+                Some(path) if sqlite => Some(treescan::sqlite::Writer::create(path)
+                    .map_err(|e| io::Error::other(format!("{}: {}", path.display(), e)))?),
+                _ => None,
+            },
+            #[cfg(feature = "parquet")]
+            parquet: match &args.output {
+                Some(path) if parquet => Some(treescan::parquet::Writer::create(path)
+                    .map_err(|e| io::Error::other(format!("{}: {}", path.display(), e)))?),
+                _ => None,
+            },
+        };
+        if html {
+            output.out.write_all(HTML_HEAD.as_bytes())?;
+        }
+        Ok(output)
+    }
+
+
+    /// Whether headers, totals and errors go in the output itself.
+    fn decorated(&self) -> bool {
+        self.args.format == Format::Table && !self.args.null && self.args.verify.is_none()
+    }
+
+
+    fn event(&mut self, event: &Event) -> io::Result<()> {
+        if let (Event::Root(root), Some(template)) = (event, &self.args.output_per_root) {
+            let name = match self.labels.get(root) {
+                Some(label) => root_file_name(label),
+                None => root_file_name(&root.to_string_lossy()),
+            };
+            self.next_file(template.replace("{root}", &name).into())?;
+        }
+        if self.args.count_only {
+            return self.count_event(event);
+        }
+        let table = self.decorated();
+        let headers = table && !self.args.quiet && !self.args.tree;
+
+        match event {
+            // the header was held back and nothing came to go under it
+            Event::DirEnd(_) if self.pruned => {
+                self.resume = None;
+                self.pruned = false;
+                return Ok(());
+            }
+            Event::Entry(_) => self.resume_header()?,
+            Event::DirEnd(_) if self.args.dir_totals => self.resume_header()?,
+            // printed elsewhere, so the listing carries on as it was
+            Event::Error(_) | Event::Unreadable(..) => {}
+            _ => {
+                self.resume = None;
+                self.pruned = false;
+            }
+        }
+
+        if let Event::Root(root) = event {
+            self.label = self.labels.get(root).cloned();
+        }
+        if self.args.format == Format::JsonStream && !self.args.null {
+            self.stream_event(event)?;
+        }
+
+        match event {
+            Event::Root(root) if headers => {
+                self.root.clone_from(root);
+                writeln!(self.out, "{}", "-".repeat(40))?;
+                writeln!(self.out, "(root) {}:", self.root_str())?;
+            }
+            Event::Dir(dir) if headers && self.args.prune_empty_dirs => {
+                self.resume = Some(dir.clone());
+                self.pruned = true;
+            }
+            Event::Dir(dir) if headers => {
+                writeln!(self.out)?;
+                writeln!(self.out, "{}/:", path_str(self.args, dir))?;
+            }
+            Event::DirResume(dir) if headers => self.resume = Some(dir.clone()),
+            Event::Entry(entry) => self.entry(entry)?,
+            // counted in run() instead
+            Event::Error(_) | Event::Unreadable(..) if self.args.quiet || self.expected_error(event) => {}
+            // on stderr, whatever the format, to keep the output parseable
+            Event::Error(_) | Event::Unreadable(..) => self.print_error(event),
+            Event::DirEnd(bytes) if table && self.args.dir_totals => {
+                writeln!(self.out, "dir {}: {}", bytes_label(self.args), size_str(self.args, *bytes))?;
+            }
+            Event::RootEnd(total) if table => {
+                writeln!(self.out, "total {}: {}", bytes_label(self.args), size_str(self.args, *total))?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+
+    /// The enter and leave records around each listing.  The listings
+    /// come depth first, so each end is the innermost one's.
+    fn stream_event(&mut self, event: &Event) -> io::Result<()> {
+        let record = match event {
+            Event::Root(root) => {
+                self.open.push(String::new());
+                let root = self.label.clone().unwrap_or_else(|| path_str(self.args, root));
+                StreamDir { event: "enter", path: String::new(), root: Some(root), bytes: None }
+            }
+            Event::Dir(dir) => {
+                let path = path_str(self.args, dir);
+                self.open.push(path.clone());
+                StreamDir { event: "enter", path, root: None, bytes: None }
+            }
+            Event::DirEnd(bytes) => match self.open.pop() {
+                Some(path) => StreamDir { event: "leave", path, root: None, bytes: Some(*bytes) },
+                None => return Ok(()),
+            },
+            // whatever wasn't listed, with --max-depth 0 say, ends here
+            Event::RootEnd(_) => {
+                while let Some(path) = self.open.pop() {
+                    let record = StreamDir { event: "leave", path, root: None, bytes: None };
+                    writeln!(self.out, "{}", serde_json::to_string(&record)?)?;
+                }
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        writeln!(self.out, "{}", serde_json::to_string(&record)?)
+    }
+
+
+    /// With --error-output, record an error event there.
+    fn error_record(&mut self, event: &Event) -> io::Result<()> {
+        let out = match &mut self.error_out {
+            Some(out) => out,
+            None => return Ok(()),
+        };
+        let (path, error) = match event {
+            // the path goes in its own field rather than the message
+            Event::Error(err) => (err.path(), err.io_error().map_or(err.to_string(), |e| e.to_string())),
+            Event::Unreadable(path, err) => (Some(path.as_path()), err.to_string()),
+            _ => return Ok(()),
+        };
+        let path = path.map(|path| path_str(self.args, path));
+        writeln!(out, "{}", serde_json::json!({ "error": error, "path": path }))
+    }
+
+
+    /// An error event's line on stderr.
+    fn print_error(&self, event: &Event) {
+        let line = match event {
+            Event::Error(err) => format!("err {:?}", err),
+            Event::Unreadable(path, err) => format!("treescan: {}: {}", path.display(), err),
+            _ => return,
+        };
+        if self.args.ascii_only {
+            eprintln!("{}", ascii_text(&line));
+        }
+        else {
+            eprintln!("{}", line);
+        }
+    }
+
+
+    /// Whether an error is for a path --quiet-errors-for covers.
+    fn expected_error(&self, event: &Event) -> bool {
+        let path = match event {
+            Event::Error(err) => err.path(),
+            Event::Unreadable(path, _) => Some(path.as_path()),
+            _ => None,
+        };
+        path.is_some_and(|path| self.quiet_errors.is_match(path))
+    }
+
+
+    /// The current root as its header shows it.
+    fn root_str(&self) -> String {
+        match &self.label {
+            Some(label) => label.clone(),
+            None => path_str(self.args, &self.root),
+        }
+    }
+
+
+    /// The little --count-only shows of an event: root totals, and
+    /// errors on stderr.
+    fn count_event(&mut self, event: &Event) -> io::Result<()> {
+        match event {
+            Event::RootEnd(total) => {
+                writeln!(self.out, "total {}: {}", bytes_label(self.args), size_str(self.args, *total))?;
+            }
+            Event::Error(_) | Event::Unreadable(..) if self.args.quiet || self.expected_error(event) => {}
+            Event::Error(_) | Event::Unreadable(..) => self.print_error(event),
+            _ => {}
+        }
+        Ok(())
+    }
+
+
+    /// The --count-only finish: entry counts and the grand total.
+    fn counts(&mut self, summary: &Summary) -> io::Result<()> {
+        writeln!(self.out, "{}", "-".repeat(40))?;
+        writeln!(self.out, "files: {}  directories: {}  symlinks: {}",
+            summary.files, summary.dirs, summary.symlinks)?;
+        writeln!(self.out, "grand total {}: {}", bytes_label(self.args), size_str(self.args, summary.bytes))
+    }
+
+
+    /// Repeat the header of a listing that carries on after a nested
+    /// one, if anything's to be printed under it.
+    fn resume_header(&mut self) -> io::Result<()> {
+        self.pruned = false;
+        match self.resume.take() {
+            Some(dir) if dir.as_os_str().is_empty() => {
+                writeln!(self.out)?;
+                writeln!(self.out, "(root) {}:", self.root_str())
+            }
+            Some(dir) => {
+                writeln!(self.out)?;
+                writeln!(self.out, "{}/:", path_str(self.args, &dir))
+            }
+            None => Ok(()),
+        }
+    }
+
+
+    /// One fixed-width table line.
+    fn row(&mut self, entry: &ScanEntry) -> io::Result<()> {
+        let args = self.args;
+        let width = args.owner_width as usize;
+        let mut extra = String::new();
+
+        match entry.kind {
+            Kind::Symlink => {
+                extra.push_str(" -> ");
+                extra.push_str(entry.target.as_deref().unwrap_or_default());
+            }
+            Kind::Dir => extra.push('/'),
+            _ => {}
+        }
+        for note in &entry.notes {
+            extra.push_str(&format!(" ({})", note));
+        }
+        if let Some(error) = &entry.error {
+            extra.push_str(&format!(" (error: {})", error));
+        }
+        // unchanged entries are left unmarked, to keep the table readable
+        match entry.change {
+            Some(Change::Unchanged) | None => {}
+            Some(change) => extra.push_str(&format!(" [{}]", change.as_str())),
+        }
+
+        // wide enough for "no meta" either way
+        let mode_width = match args.mode_format {
+            ModeFormat::Symbolic => 10,
+            ModeFormat::Octal => 7,
+        };
+        // owner and group are left blank when there's no metadata
+        let known = entry.mode.is_some();
+        // room for the longest placeholder, or the digest if longer
+        let hash_width = (args.hashlen as usize + args.head_hash.is_some() as usize).max(8);
+        let time = |time: Option<&String>| format!("{:1$}", time.map_or("?", |t| t.as_str()), self.time_width);
+
+        let cells: Vec<String> = self.columns.iter().map(|column| match column {
+            Column::Inode => match entry.inode {
+                Some(inode) => format!("{:>10}", inode),
+                None => format!("{:>10}", "?"),
+            },
+            Column::Perms => format!("{:mode_width$}", entry.mode.as_deref().unwrap_or("no meta")),
+            Column::Size => format!("{:>10}", size_str(args, entry.size)),
+            Column::Owner if known => format!("{:width$}", clip_name(&entry.owner, width)),
+            Column::Group if known => format!("{:width$}", clip_name(&entry.group, width)),
+            Column::Owner | Column::Group => " ".repeat(width),
+            Column::Mtime => time(Some(&entry.mtime)),
+            Column::Atime => time(entry.atime.as_ref()),
+            Column::Ctime => time(entry.ctime.as_ref()),
+            Column::Hash => format!("{:hash_width$}", entry.hash),
+            Column::Name => {
+                let name = match args.max_name_len {
+                    Some(max) => elide_name(&entry.name, max as usize, if args.ascii_only { '~' } else { '…' }),
+                    None => Cow::Borrowed(entry.name.as_str()),
+                };
+                match kind_color(entry).filter(|_| self.color) {
+                    Some(color) => format!("{}{}{}\x1b[0m", color, name, extra),
+                    None => format!("{}{}", name, extra),
+                }
+            }
+        }).collect();
+
+        let mut line = cells.join(" ");
+        if args.tree {
+            // indent the name by the entry's depth below the root
+            if let Some(at) = self.columns.iter().position(|c| *c == Column::Name) {
+                let before: usize = cells[..at].iter().map(|cell| cell.len() + 1).sum();
+                let depth = Path::new(&entry.path).components().count().saturating_sub(1);
+                line.insert_str(before, &"  ".repeat(depth));
+            }
+        }
+        // padding is only there to line up what follows
+        let line = if self.columns.last() == Some(&Column::Name) { &line } else { line.trim_end() };
+        writeln!(self.out, "{}", line)?;
+
+        for (name, value) in &entry.xattrs {
+            writeln!(self.out, "    {} = {}", name, value)?;
+        }
+        Ok(())
+    }
+
+
+    fn entry(&mut self, entry: &ScanEntry) -> io::Result<()> {
+        if self.args.null {
+            // archive members have no path of their own
+            if entry.kind != Kind::Dir && !entry.fs_path.as_os_str().is_empty() {
+                self.out.write_all(&platform::path_bytes(&entry.fs_path))?;
+                self.out.write_all(b"\0")?;
+            }
+            return Ok(());
+        }
+
+        let entry = &*styled(self.args, entry, self.label.as_deref());
+        match self.args.format {
+            Format::Table => self.row(entry)?,
+            Format::Json => {
+                writeln!(self.out, "{}{}", if self.emitted == 0 { "[" } else { "," },
+                    serde_json::to_string(entry)?)?;
+            }
+            Format::Ndjson => writeln!(self.out, "{}", serde_json::to_string(entry)?)?,
+            Format::JsonStream => {
+                writeln!(self.out, "{}", serde_json::to_string(&StreamEntry { event: "file", entry })?)?;
+            }
+            #[cfg(feature = "sqlite")]
+            Format::Sqlite => {
+                if let Some(db) = &mut self.db {
+                    db.insert(entry).map_err(io::Error::other)?;
+                }
+            }
+            // refused in new()
+            #[cfg(not(feature = "sqlite"))]
+            Format::Sqlite => {}
+            #[cfg(feature = "parquet")]
+            Format::Parquet => {
+                if let Some(parquet) = &mut self.parquet {
+                    parquet.insert(entry).map_err(io::Error::other)?;
+                }
+            }
+            #[cfg(not(feature = "parquet"))]
+            Format::Parquet => {}
+            Format::Csv => {
+                if self.emitted == 0 {
+                    self.header(",")?;
+                }
+                let row: Vec<String> = self.fields(entry).iter()
+                    .map(|field| csv_field(field).into_owned())
+                    .collect();
+                writeln!(self.out, "{}", row.join(","))?;
+            }
+            Format::Tsv => {
+                if self.emitted == 0 && self.args.header {
+                    self.header("\t")?;
+                }
+                let row: Vec<String> = self.fields(entry).iter()
+                    .map(|field| tsv_field(field).into_owned())
+                    .collect();
+                writeln!(self.out, "{}", row.join("\t"))?;
+            }
+            Format::GitTree => {
+                if let Some(line) = git_tree_line(entry) {
+                    writeln!(self.out, "{}", line)?;
+                }
+            }
+            Format::Ls => {
+                let now = chrono::Utc::now().timestamp();
+                writeln!(self.out, "{}", ls_line(entry, self.args.owner_width as usize, self.args.local_time, now))?;
+            }
+            Format::Md5sum => {
+                if entry.kind == Kind::File && entry.has_digest() {
+                    writeln!(self.out, "{}", sum_line(&entry.hash, &entry.path))?;
+                }
+            }
+            Format::Html => self.out.write_all(html_row(self.args, entry).as_bytes())?,
+            Format::Yaml => self.out.write_all(yaml_item(entry)?.as_bytes())?,
+            Format::Toml => {
+                if self.emitted > 0 {
+                    writeln!(self.out)?;
+                }
+                self.out.write_all(toml_item(entry)?.as_bytes())?;
+            }
+        }
+        self.emitted += 1;
+        Ok(())
+    }
+
+
+    /// The CSV and TSV column names, plus `inode` with --show-inodes
+    /// and `change` when comparing.
+    fn header(&mut self, sep: &str) -> io::Result<()> {
+        if self.continued {
+            return Ok(());
+        }
+        let mut header = ROW_COLUMNS.to_vec();
+        if self.args.show_inodes {
+            header.push("inode");
+        }
+        if self.args.baseline.is_some() {
+            header.push("change");
+        }
+        writeln!(self.out, "{}", header.join(sep))
+    }
+
+
+    /// An entry's CSV and TSV fields, unescaped, to go with header().
+    fn fields(&self, entry: &ScanEntry) -> Vec<String> {
+        let mut row = vec![
+            entry.path.clone(),
+            entry.size.to_string(),
+            entry.mode.clone().unwrap_or_default(),
+            entry.owner.clone(),
+            entry.group.clone(),
+            entry.mtime.clone(),
+            entry.hash.clone(),
+            entry.kind.as_str().into(),
+            entry.target.clone().unwrap_or_default(),
+            entry.error.clone().unwrap_or_default(),
+        ];
+        if self.args.show_inodes {
+            row.push(entry.inode.map_or(String::new(), |inode| inode.to_string()));
+        }
+        if self.args.baseline.is_some() {
+            row.push(entry.change.map_or("", Change::as_str).into());
+        }
+        row
+    }
+
+
+    /// One --verify result, like `md5sum -c`; --quiet leaves out the
+    /// files that passed.
+    fn verdict(&mut self, path: &str, verdict: Verdict) -> io::Result<()> {
+        if self.args.quiet && verdict == Verdict::Ok {
+            return Ok(());
+        }
+        writeln!(self.out, "{}: {}", path, verdict.as_str())
+    }
+
+
+    /// Baseline entries that no longer exist, after everything else.
+    fn removed(&mut self, baseline: &Baseline) -> io::Result<()> {
+        // there are no paths on disk for --null to print
+        if self.args.null {
+            return Ok(());
+        }
+
+        let mut removed = baseline.removed().peekable();
+        if self.decorated() && removed.peek().is_some() {
+            writeln!(self.out, "{}", "-".repeat(40))?;
+            writeln!(self.out, "(removed):")?;
+        }
+        for entry in removed {
+            self.entry(&entry)?;
+        }
+        Ok(())
+    }
+
+
+    /// Say the scan was cut short by --max-files, in the table or else
+    /// on stderr.
+    fn limited(&mut self, max: u64) -> io::Result<()> {
+        if self.decorated() {
+            writeln!(self.out, "{}", "-".repeat(40))?;
+            writeln!(self.out, "(stopped after {} entries, --max-files)", max)
+        }
+        else {
+            eprintln!("treescan: stopped after {} entries (--max-files)", max);
+            Ok(())
+        }
+    }
+
+
+    /// The --summary block: in the output for tables, or else on stderr
+    /// so structured output stays parseable.
+    fn summary(&mut self, summary: &Summary) -> io::Result<()> {
+        if self.decorated() || self.args.summary_only {
+            write_summary(&mut self.out, self.args, summary)
+        }
+        else {
+            write_summary(&mut io::stderr(), self.args, summary)
+        }
+    }
+
+
+    /// With --format html, the totals for the top of the page.
+    fn html_summary(&mut self, summary: &Summary) -> io::Result<()> {
+        if self.args.format == Format::Html {
+            let mut totals = Vec::new();
+            write_html_summary(&mut totals, self.args, summary)?;
+            self.totals = String::from_utf8_lossy(&totals).into_owned();
+        }
+        Ok(())
+    }
+
+
+    /// The --group-by table, placed like the summary.
+    fn groups(&mut self, summary: &Summary) -> io::Result<()> {
+        if self.decorated() || self.args.summary_only {
+            write_groups(&mut self.out, self.args, summary)
+        }
+        else {
+            write_groups(&mut io::stderr(), self.args, summary)
+        }
+    }
+
+
+    /// The --top list, placed like the summary.
+    fn top(&mut self, files: &[(PathBuf, u64)]) -> io::Result<()> {
+        if self.decorated() || self.args.summary_only {
+            write_top(&mut self.out, self.args, files)
+        }
+        else {
+            write_top(&mut io::stderr(), self.args, files)
+        }
+    }
+
+
+    /// The --find-dupes sets, placed like the summary.
+    fn dupes(&mut self, groups: &[DupeGroup]) -> io::Result<()> {
+        if self.decorated() || self.args.summary_only {
+            write_dupes(&mut self.out, self.args, groups)
+        }
+        else {
+            write_dupes(&mut io::stderr(), self.args, groups)
+        }
+    }
+
+
+    /// Write out what's buffered.
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+
+    /// Close off anything the output format left open.
+    fn finish(&mut self) -> io::Result<()> {
+        // each root's file was finished as the next began
+        if self.args.output_per_root.is_none() {
+            self.end_listing()?;
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = self.db.take() {
+            db.finish().map_err(io::Error::other)?;
+        }
+        #[cfg(feature = "parquet")]
+        if let Some(parquet) = self.parquet.take() {
+            parquet.finish().map_err(io::Error::other)?;
+        }
+        if let Some(out) = &mut self.error_out {
+            out.flush()?;
+        }
+        self.out.flush()
+    }
+
+
+    /// Whatever closes the listing in the format at hand, such as the
+    /// end of a JSON array.
+    fn end_listing(&mut self) -> io::Result<()> {
+        if !self.args.null && !self.args.summary_only {
+            match self.args.format {
+                Format::Json => writeln!(self.out, "{}", if self.emitted == 0 { "[]" } else { "]" })?,
+                Format::Csv if self.emitted == 0 => self.header(",")?,
+                Format::Tsv if self.emitted == 0 && self.args.header => self.header("\t")?,
+                Format::Yaml if self.emitted == 0 => writeln!(self.out, "[]")?,
+                Format::Html => {
+                    self.out.write_all(HTML_TABLE_END.as_bytes())?;
+                    self.out.write_all(std::mem::take(&mut self.totals).as_bytes())?;
+                    self.out.write_all(HTML_TAIL.as_bytes())?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+
+    /// For --output-per-root: finish the last root's file, if any, and
+    /// carry on in `path`, or in a numbered variant if two roots come
+    /// to the same name.
+    fn next_file(&mut self, path: PathBuf) -> io::Result<()> {
+        self.end_roots()?;
+
+        let mut unique = path.clone();
+        let mut n = 1;
+        while self.root_files.contains(&unique) {
+            n += 1;
+            let mut name = path.file_stem().unwrap_or_default().to_os_string();
+            name.push(format!("-{}", n));
+            if let Some(ext) = path.extension() {
+                name.push(".");
+                name.push(ext);
+            }
+            unique = path.with_file_name(name);
+        }
+        let file = File::create(&unique)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", unique.display(), e)))?;
+        self.out = BufWriter::with_capacity(64*1024, self.writer(Box::new(file)));
+        self.root_files.insert(unique);
+        self.emitted = 0;
+        Ok(())
+    }
+
+
+    /// `out`, kept to ASCII if asked.
+    fn writer(&self, out: Box<dyn Write>) -> Box<dyn Write> {
+        if self.args.ascii_only {
+            Box::new(AsciiOnly::new(out))
+        }
+        else {
+            out
+        }
+    }
+
+
+    /// For --output-per-root, finish the current root's file, so what
+    /// comes after the listings goes to stdout.
+    fn end_roots(&mut self) -> io::Result<()> {
+        if self.args.output_per_root.is_none() || self.root_files.is_empty() {
+            return Ok(());
+        }
+        self.end_listing()?;
+        self.out.flush()?;
+        self.out = BufWriter::with_capacity(64*1024, self.writer(Box::new(io::stdout())));
+        Ok(())
+    }
+}
+
+
+/// `{root}` for --output-per-root: anything but ASCII letters, digits,
+/// `-`, `_` and `.` becomes `_`, with separators at the ends dropped, so
+/// `/srv/my data` is `srv_my_data`.  `/` and `.` are `root`.
+fn root_file_name(root: &str) -> String {
+    let name: String = root.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect();
+    let name = name.trim_matches(|c| c == '_' || c == '.');
+    if name.is_empty() { "root".into() } else { name.into() }
+}
+
+
+/// Writing through to another writer, with everything that isn't ASCII
+/// escaped as JSON would: `\u00e9`, or a surrogate pair past U+FFFF.
+/// Bytes that aren't UTF-8 at all become `\xHH`.
+struct AsciiOnly<W> {
+    inner: W,
+    /// The start of a character split between writes.
+    partial: Vec<u8>,
+}
+
+
+impl<W: Write> AsciiOnly<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, partial: Vec::new() }
+    }
+}
+
+
+impl<W: Write> Write for AsciiOnly<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        let mut out = String::new();
+        let mut rest = &self.partial[..];
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(text) => {
+                    out.push_str(&ascii_text(text));
+                    rest = &[];
+                    break;
+                }
+                Err(e) => {
+                    let (good, bad) = rest.split_at(e.valid_up_to());
+                    out.push_str(&ascii_text(std::str::from_utf8(good).unwrap_or_default()));
+                    match e.error_len() {
+                        Some(len) => {
+                            for b in &bad[..len] {
+                                out.push_str(&format!("\\x{:02x}", b));
+                            }
+                            rest = &bad[len..];
+                        }
+                        // cut short: the rest may come with the next write
+                        None => {
+                            rest = bad;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.inner.write_all(out.as_bytes())?;
+        self.partial = rest.to_vec();
+        Ok(buf.len())
+    }
+
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+
+/// `text` with anything that isn't ASCII escaped as for AsciiOnly.
+fn ascii_text(text: &str) -> Cow<'_, str> {
+    if text.is_ascii() {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len() + 16);
+    for c in text.chars() {
+        if c.is_ascii() {
+            out.push(c);
+        }
+        else {
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                out.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+    Cow::Owned(out)
+}
+
+
+/// Saving --checkpoint progress, at most every few seconds.
+struct Saver<'a> {
+    path: &'a Path,
+    last: Instant,
+}
+
+
+impl Saver<'_> {
+    const EVERY: Duration = Duration::from_secs(5);
+
+    /// Save any progress if it's been long enough, or regardless with
+    /// `now`.  The output is flushed first, so a checkpoint never counts
+    /// as finished anything that hasn't been written.
+    fn update(&mut self, scanner: &mut Scanner, output: &mut Output, now: bool) -> io::Result<()> {
+        let checkpoint = scanner.checkpoint();
+        if !checkpoint.changed() || (!now && self.last.elapsed() < Self::EVERY) {
+            return Ok(());
+        }
+        output.flush()?;
+        checkpoint.save(self.path)
+            .map_err(|e| io::Error::new(e.kind(), format!("checkpoint {}: {}", self.path.display(), e)))?;
+        self.last = Instant::now();
+        Ok(())
+    }
+}
+
+
+/// The largest files seen so far, for --top: at most `limit`, in a heap
+/// with the one to drop next (the smallest, or last by path) on top.
+struct Largest {
+    limit: usize,
+    heap: BinaryHeap<Reverse<(u64, Reverse<PathBuf>)>>,
+}
+
+
+impl Largest {
+    fn new(limit: usize) -> Self {
+        Self { limit, heap: BinaryHeap::with_capacity(limit + 1) }
+    }
+
+
+    fn add(&mut self, path: &Path, size: u64) {
+        if self.heap.len() == self.limit {
+            match self.heap.peek() {
+                Some(Reverse((least, Reverse(last)))) if (size, Reverse(path)) > (*least, Reverse(last.as_path())) => {}
+                _ => return,
+            }
+        }
+        self.heap.push(Reverse((size, Reverse(path.to_path_buf()))));
+        if self.heap.len() > self.limit {
+            self.heap.pop();
+        }
+    }
+
+
+    /// Biggest first, then by path.
+    fn into_sorted(self) -> Vec<(PathBuf, u64)> {
+        self.heap.into_sorted_vec().into_iter().map(|Reverse((size, Reverse(path)))| (path, size)).collect()
+    }
+}
+
+
+/// The --progress status line, redrawn at most a few times a second.
+struct Progress {
+    last: Option<Instant>,
+}
+
+
+impl Progress {
+    const EVERY: Duration = Duration::from_millis(250);
+
+    fn update(&mut self, scanner: &Scanner) {
+        if self.last.is_some_and(|last| last.elapsed() < Self::EVERY) {
+            return;
+        }
+        self.last = Some(Instant::now());
+
+        let summary = scanner.summary();
+        let entries = summary.files + summary.dirs + summary.symlinks + summary.others;
+        // keep it to one line so \r can redraw it
+        let dir = scanner.current_dir().to_string_lossy();
+        let len = dir.chars().count();
+        let dir: String = if len > 50 {
+            std::iter::once('~').chain(dir.chars().skip(len - 49)).collect()
+        }
+        else {
+            dir.into_owned()
+        };
+        eprint!("\r\x1b[K{} entries, {} hashed, in {}", entries, human_size(summary.hashed), dir);
+    }
+
+
+    fn clear(&mut self) {
+        if self.last.take().is_some() {
+            eprint!("\r\x1b[K");
+        }
+    }
+}
+
+
+/// The --timing line: time taken, entries and bytes hashed, and rates.
+fn timing(elapsed: Duration, summary: &Summary) -> String {
+    let entries = summary.files + summary.dirs + summary.symlinks + summary.others;
+    // short of a millisecond, the rates would mean nothing
+    let secs = elapsed.as_secs_f64().max(0.001);
+    format!("{:.2}s, {} entries ({:.0}/s), {} hashed ({}/s)", elapsed.as_secs_f64(), entries,
+        entries as f64 / secs, human_size(summary.hashed), human_size((summary.hashed as f64 / secs) as u64))
+}
+
+
+/// --quiet-errors-for's patterns, matched as --ignore-case says.
+fn quiet_globs(args: &Args) -> io::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pat in &args.quiet_errors_for {
+        let glob = GlobBuilder::new(pat).case_insensitive(args.ignore_case).build()
+            .map_err(|e| io::Error::other(format!("bad --quiet-errors-for pattern: {}", e)))?;
+        builder.add(glob);
+    }
+    builder.build().map_err(io::Error::other)
+}
+
+
+/// Scan and print everything, returning how many entries couldn't be
+/// read or failed --verify.
+fn run(args: &Args) -> Result<u64, Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    let jobs = args.jobs.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+    let whole_digests = args.verify.is_some() || args.format == Format::Md5sum;
+    let opts = ScanOptions {
+        debug: args.debug,
+        max_sum_size: args.maxsumsize * 1024*1024,
+        // verifying and checksum lists need whole digests of every file
+        hash_len: if whole_digests { args.hash.digest_len() } else { args.hashlen as usize },
+        hash: args.hash,
+        no_hash: args.no_hash || args.count_only,
+        stat_only: args.stat_only,
+        full_hash: args.full_hash || whole_digests,
+        // md5sum lists empty files by their digest too
+        hash_empty: args.hash_empty || whole_digests,
+        head_hash: args.head_hash,
+        jobs,
+        threads: args.threads as usize,
+        root_jobs: args.root_jobs as usize,
+        read_chunk: args.read_chunk,
+        mmap: args.mmap,
+        exclude: args.exclude.clone(),
+        include: args.include.clone(),
+        gitignore: args.gitignore,
+        skip_hidden: args.skip_hidden,
+        ignore_case: args.ignore_case,
+        ignore_file: !args.no_ignore_file,
+        max_depth: args.max_depth,
+        min_size: match args.min_size {
+            Some(min) if args.exclude_size_zero => Some(min.max(1)),
+            None if args.exclude_size_zero => Some(1),
+            min => min,
+        },
+        max_size: args.max_size,
+        since: args.since,
+        until: args.until,
+        owner: args.owner.clone(),
+        group: args.group.clone(),
+        mode_mask: args.mode_mask,
+        mode_match: args.mode_match,
+        retries: args.retries,
+        limit_rate: args.limit_rate,
+        empty: args.empty,
+        max_files: args.max_files,
+        cross_device: args.cross_device,
+        resolve_mountpoints: args.resolve_mountpoints,
+        follow_symlinks: args.follow_symlinks,
+        follow_only: args.follow_only.clone(),
+        dedup_hardlinks: args.dedup_hardlinks,
+        count_symlinks: args.count_symlinks,
+        deref_info: args.deref_info,
+        into_archives: args.into_archives,
+        blocks: args.blocks,
+        sort: args.sort,
+        reverse: args.reverse,
+        tree_order: args.tree || args.order == Order::Dfs || args.format == Format::JsonStream,
+        mode_format: args.mode_format,
+        numeric_ids: args.numeric_ids,
+        snapshot_ids: args.snapshot_ids,
+        escape_paths: args.escape_paths,
+        hash_names: args.hash_names,
+        strip_components: args.strip_components,
+        time_format: time_format(args).into(),
+        local_time: args.local_time,
+        times: wanted_times(args),
+        show_inodes: args.show_inodes || args.columns.contains(&Column::Inode),
+        xattrs: args.xattrs,
+        caps: args.caps,
+        special_bits: args.flag_special_bits,
+    };
+
+    let mut scanner = Scanner::new(opts)?;
+
+    let mut paths = args.paths.clone();
+    if let Some(list) = &args.paths_from {
+        let input: Box<dyn BufRead> = if list.as_os_str() == "-" {
+            Box::new(io::stdin().lock())
+        }
+        else {
+            Box::new(BufReader::new(File::open(list)
+                .map_err(|e| format!("{}: {}", list.display(), e))?))
+        };
+        paths.extend(read_paths(input, args.from0)?);
+    }
+    else if paths.is_empty() {
+        paths.push(".".into());
+    }
+    let labels = root_labels(args, &paths)?;
+    if args.sort_roots {
+        paths.sort();
+    }
+    for path in paths {
+        scanner.add_root(path);
+    }
+
+    if let Some(path) = &args.resume {
+        scanner.resume(Checkpoint::load(path)
+            .map_err(|e| format!("resume {}: {}", path.display(), e))?);
+    }
+    let mut saver = args.checkpoint.as_deref().or(args.resume.as_deref())
+        .map(|path| Saver { path, last: Instant::now() });
+
+    let mut baseline = match &args.baseline {
+        Some(path) => Some(Baseline::load(path)
+            .map_err(|e| format!("baseline {}: {}", path.display(), e))?),
+        None => None,
+    };
+    if let Some(baseline) = baseline.as_ref().filter(|_| args.reuse_digests) {
+        scanner.reuse_digests(baseline.digests());
+    }
+    let mut manifest = match &args.verify {
+        Some(path) => Some(Manifest::load(path, args.hash)
+            .map_err(|e| format!("verify {}: {}", path.display(), e))?),
+        None => None,
+    };
+    let mut failed = 0;
+
+    // (path, size) of every file, for --find-dupes; repeat hard links
+    // are the same file, not copies
+    let mut files = Vec::new();
+    let mut largest = args.top.map(Largest::new);
+
+    let mut progress = Progress { last: None };
+    let mut output = Output::new(args, labels)?;
+    while let Some(mut event) = scanner.next() {
+        if args.progress {
+            match event {
+                // so the message doesn't land mid-line
+                Event::Error(_) | Event::Unreadable(..) => progress.clear(),
+                _ => progress.update(&scanner),
+            }
+        }
+        if let Event::Entry(entry) = &event {
+            let on_disk = !entry.fs_path.as_os_str().is_empty();
+            let repeat = entry.notes.iter().any(|n| n == "hardlink");
+            if args.find_dupes && on_disk && entry.kind == Kind::File && !repeat {
+                files.push((entry.fs_path.clone(), entry.size));
+            }
+            if let (Some(largest), Kind::File, false) = (&mut largest, entry.kind, repeat) {
+                // as in the summary's largest
+                if on_disk && !args.hash_names {
+                    largest.add(&entry.fs_path, entry.size);
+                }
+                else {
+                    largest.add(Path::new(&entry.path), entry.size);
+                }
+            }
+        }
+        output.error_record(&event)?;
+        if let (Some(baseline), Event::Entry(entry)) = (&mut baseline, &mut event) {
+            entry.change = Some(baseline.compare(entry));
+        }
+        // with --verify, entries are only reported by their verdicts
+        if let (Some(manifest), Event::Entry(entry)) = (&mut manifest, &event) {
+            if let Some(verdict) = manifest.check(entry) {
+                failed += (verdict != Verdict::Ok) as u64;
+                if !args.summary_only {
+                    output.verdict(&entry.path, verdict)?;
+                }
+            }
+        }
+        else if !args.summary_only {
+            output.event(&event)?;
+        }
+        if let Some(saver) = &mut saver {
+            saver.update(&mut scanner, &mut output, false)?;
+        }
+        if args.strict && scanner.errors() > 0 {
+            break;
+        }
+    }
+    output.end_roots()?;
+    if let Some(baseline) = &baseline {
+        if !args.summary_only {
+            output.removed(baseline)?;
+        }
+    }
+    if let Some(manifest) = &manifest {
+        for path in manifest.missing() {
+            failed += 1;
+            if !args.summary_only {
+                output.verdict(path, Verdict::Missing)?;
+            }
+        }
+    }
+    progress.clear();
+
+    if let (true, Some(max)) = (scanner.truncated(), args.max_files) {
+        output.limited(max)?;
+    }
+    if args.find_dupes {
+        // a bucket of its own, as the scan's reads are over by now
+        let throttle = args.limit_rate.map(Throttle::new);
+        let (groups, unread) = find_dupes(&files, args.hash, jobs, args.read_chunk, args.mmap, throttle.as_ref());
+        for (path, e) in unread {
+            failed += 1;
+            let event = Event::Unreadable(path, e);
+            output.error_record(&event)?;
+            output.event(&event)?;
+        }
+        output.dupes(&groups)?;
+    }
+    if let Some(largest) = largest {
+        output.top(&largest.into_sorted())?;
+    }
+    if args.group_by == Some(GroupBy::Ext) {
+        output.groups(scanner.summary())?;
+    }
+    if args.count_only {
+        output.counts(scanner.summary())?;
+    }
+    if args.summary || args.summary_only {
+        output.summary(scanner.summary())?;
+    }
+    output.html_summary(scanner.summary())?;
+    output.finish()?;
+    if let Some(saver) = &mut saver {
+        saver.update(&mut scanner, &mut output, true)?;
+    }
+
+    if args.timing {
+        eprintln!("treescan: {}", timing(started.elapsed(), scanner.summary()));
+    }
+    if args.quiet && scanner.errors() > 0 {
+        eprintln!("treescan: {} entries couldn't be read", scanner.errors());
+    }
+    Ok(scanner.errors() + failed)
+}
+
+
+fn main() {
+    let args = Args::from_args();
+
+    match run(&args) {
+        Ok(0) => {}
+        // a partial scan: say so to scripts
+        Ok(_) => std::process::exit(1),
+        Err(e) => {
+            // a closed pipe (e.g. `| head`) isn't worth a complaint
+            let quiet = matches!(e.downcast_ref::<io::Error>(), Some(e) if e.kind() == io::ErrorKind::BrokenPipe);
+            if !quiet {
+                eprintln!("treescan: {}", e);
+            }
+            std::process::exit(2);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn time_columns() {
+        let args = Args::try_parse_from(["treescan", "--times", "c,m"]).unwrap();
+        assert_eq!(args.times, [TimeKind::Changed, TimeKind::Modified]);
+        let args = Args::try_parse_from(["treescan"]).unwrap();
+        assert_eq!(args.times, [TimeKind::Modified]);
+        assert!(Args::try_parse_from(["treescan", "--times", "m,z"]).is_err());
+    }
+
+    #[test]
+    fn csv_quoting() {
+        assert_eq!(csv_field("plain.txt"), "plain.txt");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn tsv_escaping() {
+        // the inverse, as a reader of the output would write it
+        let unescape = |text: &str| {
+            let mut out = String::new();
+            let mut chars = text.chars();
+            while let Some(c) = chars.next() {
+                out.push(match c {
+                    '\\' => match chars.next() {
+                        Some('t') => '\t',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('\\') => '\\',
+                        other => panic!("bad escape {:?}", other),
+                    },
+                    c => c,
+                });
+            }
+            out
+        };
+
+        assert!(matches!(tsv_field("plain name.txt"), Cow::Borrowed(_)));
+        assert_eq!(tsv_field("a\tb\nc\rd"), "a\\tb\\nc\\rd");
+        assert_eq!(tsv_field("back\\slash"), "back\\\\slash");
+        for name in ["", "a\tb", "\\t", "\\\t", "x\\", "\n\n", "\\n\n", "é\t\\r\r", "\\\\n"] {
+            let field = tsv_field(name);
+            assert!(!field.contains(['\t', '\n', '\r']), "{:?}", field);
+            assert_eq!(unescape(&field), name);
+        }
+    }
+
+    #[test]
+    fn yaml_and_toml() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("f"), "hello\n").unwrap();
+        let mut entry = treescan::scan_path(tmp.path(), &ScanOptions::default()).unwrap().next().unwrap();
+        entry.mode = Some("-rw-r--r--".into());
+        entry.owner = "root".into();
+        entry.group = "wheel".into();
+        entry.mtime = "2022-07-01T12:00".into();
+        entry.mtime_ns = Some(1656676800 * 1_000_000_000);
+
+        assert_eq!(yaml_item(&entry).unwrap(), "- path: f\n  size: 6\n  mode: -rw-r--r--\n  owner: root\n  group: wheel\n  \
+            mtime: 2022-07-01T12:00\n  mtime_ns: 1656676800000000000\n  hash: b1946ac9\n  hash_algo: md5\n  target: null\n  kind: file\n");
+        assert_eq!(toml_item(&entry).unwrap(), "[[entry]]\npath = \"f\"\nsize = 6\nmode = \"-rw-r--r--\"\n\
+            owner = \"root\"\ngroup = \"wheel\"\nmtime = \"2022-07-01T12:00\"\nmtime_ns = 1656676800000000000\n\
+            hash = \"b1946ac9\"\nhash_algo = \"md5\"\nkind = \"file\"\n");
+
+        entry.xattrs.insert("user.a".into(), "b".into());
+        let doc: toml::Value = toml::from_str(&toml_item(&entry).unwrap()).unwrap();
+        assert_eq!(doc["entry"][0]["xattrs"]["user.a"].as_str(), Some("b"));
+    }
+
+    #[test]
+    fn clipped_names() {
+        assert_eq!(clip_name("root", 8), "root");
+        assert_eq!(clip_name("abcdefgh", 8), "abcdefgh");
+        assert_eq!(clip_name("abcdefghi", 8), "~cdefghi");
+        assert_eq!(clip_name("firstname.lastname", 12), "~me.lastname");
+        assert_eq!(clip_name("jürgen", 4), "~gen");
+        assert_eq!(clip_name("ab", 1), "~");
+    }
+
+    #[test]
+    fn elided_names() {
+        assert_eq!(elide_name("short.txt", 12, '…'), "short.txt");
+        assert_eq!(elide_name("verylongprefix-and-suffix.txt", 20, '…'), "verylongpr…uffix.txt");
+        assert_eq!(elide_name("abcdefghij", 5, '…'), "ab…ij");
+        // an extension too long to keep whole is treated as any other tail
+        assert_eq!(elide_name("a.verylongextension", 6, '…'), "a.v…on");
+        assert_eq!(elide_name(".hidden-file-name", 9, '…'), ".hid…name");
+        assert_eq!(elide_name("jürgenjürgen.md", 8, '…'), "jürg….md");
+        assert_eq!(elide_name("abc", 2, '…'), "a…");
+        for max in 2..30 {
+            assert_eq!(elide_name("some-rather-long-name.tar.gz", max, '…').chars().count(), max.min(28));
+        }
+    }
+
+    #[test]
+    fn path_styles() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("sub")).unwrap();
+        std::fs::write(tmp.path().join("sub/f"), "").unwrap();
+        let entry = treescan::scan_path(tmp.path(), &ScanOptions::default()).unwrap()
+            .find(|entry| entry.kind == Kind::File).unwrap();
+
+        let shown = |opts: &[&str]| {
+            let args = Args::try_parse_from(["treescan"].iter().chain(opts)).unwrap();
+            let entry = styled(&args, &entry, None);
+            if args.format == Format::Table { entry.name.clone() } else { entry.path.clone() }
+        };
+        assert_eq!(shown(&[]), "f");
+        assert_eq!(shown(&["--path-style", "relative"]), "sub/f");
+        assert_eq!(shown(&["--format", "json"]), "sub/f");
+        assert_eq!(shown(&["--format", "json", "--path-style", "name"]), "f");
+        assert_eq!(shown(&["--format", "csv", "--path-style", "absolute"]),
+                   tmp.path().join("sub/f").to_str().unwrap());
+
+        let args = Args::try_parse_from(["treescan", "--format", "json", "--path-style", "absolute"]).unwrap();
+        assert_eq!(styled(&args, &entry, Some("src")).path, "src/sub/f");
+    }
+
+    #[test]
+    fn error_records() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("errors");
+        let out = tmp.path().join("out");
+        let args = Args::try_parse_from(["treescan", "--format", "ndjson", "-o", out.to_str().unwrap(),
+            "--error-output", file.to_str().unwrap()]).unwrap();
+
+        let mut output = Output::new(&args, HashMap::new()).unwrap();
+        let gone = io::Error::new(io::ErrorKind::NotFound, "gone");
+        output.error_record(&Event::Unreadable("a/b".into(), gone)).unwrap();
+        output.error_record(&Event::DirEnd(0)).unwrap();
+        output.finish().unwrap();
+
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "{\"error\":\"gone\",\"path\":\"a/b\"}\n");
+    }
+
+    #[test]
+    fn csv_errors() {
+        let tmp = tempfile::tempdir().unwrap();
+        let out = tmp.path().join("out");
+        std::fs::write(tmp.path().join("f"), "hello\n").unwrap();
+        let mut entry = treescan::scan_path(tmp.path(), &ScanOptions::default()).unwrap().next().unwrap();
+        entry.error = Some("Permission denied, oddly".into());
+
+        let args = Args::try_parse_from(["treescan", "--format", "csv", "-o", out.to_str().unwrap()]).unwrap();
+        let mut output = Output::new(&args, HashMap::new()).unwrap();
+        output.event(&Event::Entry(Box::new(entry))).unwrap();
+        output.finish().unwrap();
+        let text = std::fs::read_to_string(&out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].ends_with(",target,error"));
+        assert!(lines[1].ends_with(",\"Permission denied, oddly\""));
+    }
+
+    #[test]
+    fn resumed_output() {
+        let tmp = tempfile::tempdir().unwrap();
+        let out = tmp.path().join("out");
+        std::fs::write(&out, "earlier\n").unwrap();
+        let args = |format| Args::try_parse_from(["treescan", "--format", format, "-o", out.to_str().unwrap(),
+            "--resume", "checkpoint"]).unwrap();
+
+        for format in ["json", "yaml", "toml", "sqlite", "parquet"] {
+            assert!(Output::new(&args(format), HashMap::new()).is_err());
+        }
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "earlier\n");
+
+        std::fs::create_dir(tmp.path().join("tree")).unwrap();
+        std::fs::write(tmp.path().join("tree/f"), "hello\n").unwrap();
+        let entry = treescan::scan_path(&tmp.path().join("tree"), &ScanOptions::default()).unwrap().next().unwrap();
+        let args = args("csv");
+        let mut output = Output::new(&args, HashMap::new()).unwrap();
+        output.event(&Event::Entry(Box::new(entry))).unwrap();
+        output.finish().unwrap();
+        let text = std::fs::read_to_string(&out).unwrap();
+        assert_eq!(text.lines().count(), 2);
+        assert!(text.lines().nth(1).unwrap().starts_with("f,"));
+    }
+
+    #[test]
+    fn json_stream() {
+        let tmp = tempfile::tempdir().unwrap();
+        let out = tmp.path().join("out");
+        let args = Args::try_parse_from(["treescan", "--format", "json-stream", "-o", out.to_str().unwrap()]).unwrap();
+
+        let mut output = Output::new(&args, HashMap::new()).unwrap();
+        for event in [Event::Root("/r".into()), Event::Dir("a".into()), Event::DirEnd(3),
+                      Event::DirResume("".into()), Event::DirEnd(5), Event::RootEnd(8)] {
+            output.event(&event).unwrap();
+        }
+        output.finish().unwrap();
+
+        let records: Vec<serde_json::Value> = std::fs::read_to_string(&out).unwrap().lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records, [
+            serde_json::json!({ "event": "enter", "path": "", "root": "/r" }),
+            serde_json::json!({ "event": "enter", "path": "a" }),
+            serde_json::json!({ "event": "leave", "path": "a", "bytes": 3 }),
+            serde_json::json!({ "event": "leave", "path": "", "bytes": 5 }),
+        ]);
+    }
+
+    #[test]
+    fn top_files() {
+        let mut largest = Largest::new(3);
+        for (path, size) in [("d", 5), ("a", 1), ("c", 9), ("b", 5), ("e", 5), ("f", 2)] {
+            largest.add(Path::new(path), size);
+        }
+        let paths: Vec<(PathBuf, u64)> = ["c", "b", "d"].iter().zip([9, 5, 5]).map(|(p, s)| (p.into(), s)).collect();
+        assert_eq!(largest.into_sorted(), paths);
+
+        let mut none = Largest::new(0);
+        none.add(Path::new("a"), 1);
+        assert!(none.into_sorted().is_empty());
+
+        let args = Args::try_parse_from(["treescan", "--top", "3", "--human"]).unwrap();
+        let mut out = Vec::new();
+        write_top(&mut out, &args, &[("big".into(), 2048), ("small".into(), 3)]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap().lines().skip(1).collect::<Vec<_>>(),
+            ["largest files:", "          2.0K big", "             3 small"]);
+    }
+
+    #[test]
+    fn html_page() {
+        let tmp = tempfile::tempdir().unwrap();
+        let out = tmp.path().join("report.html");
+        std::fs::create_dir(tmp.path().join("in")).unwrap();
+        std::fs::write(tmp.path().join("in/a<b>&c.txt"), "hello\n").unwrap();
+        let args = Args::try_parse_from(["treescan", "--format", "html", "-o", out.to_str().unwrap()]).unwrap();
+
+        let mut output = Output::new(&args, HashMap::new()).unwrap();
+        let mut found = treescan::scan_path(&tmp.path().join("in"), &ScanOptions::default()).unwrap();
+        let entry = found.next().unwrap();
+        output.event(&Event::Entry(Box::new(entry))).unwrap();
+        output.html_summary(&Summary { files: 1, bytes: 6, ..Default::default() }).unwrap();
+        output.finish().unwrap();
+
+        let page = std::fs::read_to_string(&out).unwrap();
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(page.contains("<tr><td>a&lt;b&gt;&amp;c.txt</td><td>file</td><td class=\"num\" data-sort=\"6\">6</td>"));
+        assert!(!page.contains("a<b>"));
+        // the totals come after the table, for CSS to move to the top
+        let table_end = page.find("</table>").unwrap();
+        assert!(page.find("<section id=\"summary\">").unwrap() > table_end);
+        assert!(page.contains("<p>1 files, 0 directories, 0 symlinks, 0 other; total bytes: 6</p>"));
+        assert!(page.trim_end().ends_with("</html>"));
+
+        assert_eq!(html_escape("it's \"x\""), "it&#39;s &quot;x&quot;");
+        assert!(matches!(html_escape("plain"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn prune_empty_dirs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let out = tmp.path().join("out");
+        let args = Args::try_parse_from(["treescan", "--prune-empty-dirs", "--dir-totals", "--order", "dfs",
+            "-o", out.to_str().unwrap()]).unwrap();
+
+        let mut output = Output::new(&args, HashMap::new()).unwrap();
+        std::fs::write(tmp.path().join("b"), "x").unwrap();
+        let sample = treescan::scan_path(tmp.path(), &ScanOptions::default()).unwrap()
+            .find(|entry| entry.name == "b").unwrap();
+        let entry = |path: &str| Event::Entry(Box::new(ScanEntry { path: path.into(), ..sample.clone() }));
+        for event in [Event::Root("/r".into()), Event::Dir("a".into()), entry("a/b"),
+                      Event::Dir("a/b".into()), Event::DirEnd(0), Event::DirResume("a".into()), Event::DirEnd(1),
+                      Event::Dir("c".into()), Event::DirEnd(0), Event::RootEnd(1)] {
+            output.event(&event).unwrap();
+        }
+        output.finish().unwrap();
+
+        let text = std::fs::read_to_string(&out).unwrap();
+        assert!(text.contains("\na/:\n"));
+        assert!(!text.contains("a/b/:"));
+        assert!(!text.contains("c/:"));
+        assert_eq!(text.matches("dir bytes").count(), 1);
+    }
+
+    #[test]
+    fn timing_line() {
+        let summary = Summary { files: 90, dirs: 10, hashed: 20 << 20, ..Default::default() };
+        assert_eq!(timing(Duration::from_secs(4), &summary), "4.00s, 100 entries (25/s), 20M hashed (5.0M/s)");
+        assert_eq!(timing(Duration::ZERO, &Summary::default()), "0.00s, 0 entries (0/s), 0 hashed (0/s)");
+    }
+
+    #[test]
+    fn ls_lines() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a"), "hello\n").unwrap();
+        let entry = treescan::scan_path(tmp.path(), &ScanOptions::default()).unwrap().next().unwrap();
+        let entry = ScanEntry { owner: "root".into(), group: "wheel".into(), mode_bits: Some(0o100644),
+            nlink: Some(2), mtime_unix: Some(1656676800), ..entry };
+
+        let now = 1656676800 + 3600;
+        assert_eq!(ls_line(&entry, 8, false, now), "-rw-r--r--   2 root     wheel           6 Jul  1 12:00 a");
+        assert_eq!(ls_line(&entry, 8, false, now + 365 * 24 * 3600), "-rw-r--r--   2 root     wheel           6 Jul  1  2022 a");
+
+        let link = ScanEntry { mode_bits: Some(0o120777), target: Some("a".into()), path: "l".into(), ..entry.clone() };
+        assert!(ls_line(&link, 8, false, now).starts_with("lrwxrwxrwx "));
+        assert!(ls_line(&link, 8, false, now).ends_with(" l -> a"));
+        let dir = ScanEntry { mode_bits: None, nlink: None, mtime_unix: None, size: 0, ..entry };
+        assert_eq!(ls_line(&dir, 4, false, now), "??????????   ? root wheel        0              a");
+    }
+
+    #[test]
+    fn git_tree() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a b"), "hello\n").unwrap();
+        let entry = treescan::scan_path(tmp.path(), &ScanOptions::default()).unwrap().next().unwrap();
+        assert_eq!(git_tree_line(&entry).unwrap(), "100644 blob b1946ac9\ta b");
+
+        let dir = ScanEntry { kind: Kind::Dir, hash: String::new(), path: "d".into(), ..entry.clone() };
+        assert_eq!(git_tree_line(&dir).unwrap(), "040000 tree -\td");
+        let exe = ScanEntry { mode_bits: Some(0o100750), ..entry.clone() };
+        assert!(git_tree_line(&exe).unwrap().starts_with("100755 blob "));
+        assert!(git_tree_line(&ScanEntry { kind: Kind::Special, ..entry }).is_none());
+
+        assert!(matches!(git_path("plain/café"), Cow::Borrowed(_)));
+        assert_eq!(git_path("tab\there"), r#""tab\there""#);
+        assert_eq!(git_path("say \"hi\"\\\x01"), r#""say \"hi\"\\\001""#);
+    }
+
+    #[test]
+    fn expected_errors() {
+        let args = Args::try_parse_from(["treescan", "--quiet-errors-for", "/proc/**"]).unwrap();
+        let output = Output::new(&args, HashMap::new()).unwrap();
+        let denied = || io::Error::from(io::ErrorKind::PermissionDenied);
+        assert!(output.expected_error(&Event::Unreadable("/proc/1/mem".into(), denied())));
+        assert!(!output.expected_error(&Event::Unreadable("/home/x".into(), denied())));
+        assert!(!output.expected_error(&Event::DirEnd(0)));
+
+        let args = Args::try_parse_from(["treescan", "--quiet-errors-for", "a{"]).unwrap();
+        assert!(Output::new(&args, HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn head_hash_sums() {
+        let args = Args::try_parse_from(["treescan", "--format", "md5sum", "--head-hash", "4K"]).unwrap();
+        assert!(Output::new(&args, HashMap::new()).is_err());
+        let args = Args::try_parse_from(["treescan", "--format", "md5sum", "--head-hash", "4K", "--null"]).unwrap();
+        assert!(Output::new(&args, HashMap::new()).is_ok());
+    }
+
+    #[test]
+    fn output_per_root() {
+        assert_eq!(root_file_name("/srv/my data"), "srv_my_data");
+        assert_eq!(root_file_name("../v1.2"), "v1.2");
+        assert_eq!(root_file_name("/"), "root");
+        assert_eq!(root_file_name("."), "root");
+
+        let tmp = tempfile::tempdir().unwrap();
+        let template = tmp.path().join("scan-{root}.json");
+        let args = Args::try_parse_from(["treescan", "--format", "json",
+            "--output-per-root", template.to_str().unwrap()]).unwrap();
+        let mut output = Output::new(&args, HashMap::new()).unwrap();
+        for root in ["/a", "a", "/b"] {
+            output.event(&Event::Root(root.into())).unwrap();
+            output.event(&Event::RootEnd(0)).unwrap();
+        }
+        output.end_roots().unwrap();
+        output.finish().unwrap();
+
+        for name in ["scan-a.json", "scan-a-2.json", "scan-b.json"] {
+            assert_eq!(std::fs::read_to_string(tmp.path().join(name)).unwrap(), "[]\n", "{}", name);
+        }
+
+        let args = Args::try_parse_from(["treescan", "--output-per-root", "scan.json"]).unwrap();
+        assert!(Output::new(&args, HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn ascii_only() {
+        assert!(matches!(ascii_text("plain"), Cow::Borrowed(_)));
+        assert_eq!(ascii_text("café ✓ 𝄞"), "caf\\u00e9 \\u2713 \\ud834\\udd1e");
+
+        let mut out = Vec::new();
+        {
+            let mut ascii = AsciiOnly::new(&mut out);
+            let text = "{\"path\":\"jürgen/𝄞\"}".as_bytes();
+            // a byte at a time, so characters are split between writes
+            for b in text {
+                ascii.write_all(&[*b]).unwrap();
+            }
+            ascii.write_all(b" \xff").unwrap();
+        }
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.is_ascii());
+        assert_eq!(text, "{\"path\":\"j\\u00fcrgen/\\ud834\\udd1e\"} \\xff");
+        let (json, _) = text.split_once(' ').unwrap();
+        let value: serde_json::Value = serde_json::from_str(json).unwrap();
+        assert_eq!(value["path"], "jürgen/𝄞");
+
+        assert_eq!(elide_name("abcdefghij", 5, '~'), "ab~ij");
+    }
+
+    #[test]
+    fn time_precision() {
+        let format = |opts: &[&str]| {
+            let args = Args::try_parse_from(["treescan"].iter().chain(opts)).unwrap();
+            time_format(&args).to_string()
+        };
+        assert_eq!(format(&[]), DEFAULT_TIME_FORMAT);
+        assert_eq!(format(&["--time-precision", "minute"]), DEFAULT_TIME_FORMAT);
+        assert_eq!(format(&["--time-precision", "second"]), "%Y-%m-%dT%H:%M:%S");
+        assert_eq!(format(&["--time-precision", "day"]), "%Y-%m-%d");
+        assert_eq!(format(&["--time-format", "%s"]), "%s");
+        assert!(Args::try_parse_from(["treescan", "--time-precision", "day", "--time-format", "%s"]).is_err());
+    }
+
+    #[test]
+    fn count_only() {
+        let args = Args::try_parse_from(["treescan", "--count-only"]).unwrap();
+        assert!(args.count_only && args.format == Format::Table);
+        for other in ["--summary-only", "--null", "--format=csv", "--verify=sums"] {
+            assert!(Args::try_parse_from(["treescan", "--count-only", other]).is_err(), "{}", other);
+        }
+    }
+
+    #[test]
+    fn labels() {
+        let roots = [PathBuf::from("/home/me/a"), "b".into()];
+        let labels = |opts: &[&str]| {
+            let args = Args::try_parse_from(["treescan"].iter().chain(opts)).unwrap();
+            root_labels(&args, &roots).map(|labels| {
+                roots.iter().map(|root| labels.get(root).cloned()).collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(labels(&[]).unwrap(), [None, None]);
+        assert_eq!(labels(&["--root-label", "x", "--root-label", "y"]).unwrap(),
+                   [Some("x".into()), Some("y".into())]);
+        assert_eq!(labels(&["--root-label", "src"]).unwrap(),
+                   [Some("src-1".into()), Some("src-2".into())]);
+        assert!(labels(&["--root-label", "x", "--root-label", "y", "--root-label", "z"]).is_err());
+    }
+
+    #[test]
+    fn path_lists() {
+        let lines = read_paths(&b"a\n\nb c\r\n/d"[..], false).unwrap();
+        assert_eq!(lines, [PathBuf::from("a"), "b c".into(), "/d".into()]);
+        let nuls = read_paths(&b"one\ntwo\0\0three\0"[..], true).unwrap();
+        assert_eq!(nuls, [PathBuf::from("one\ntwo"), "three".into()]);
+        assert!(read_paths(&b""[..], false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn column_choice() {
+        let args = Args::try_parse_from(["treescan", "--times", "a,m"]).unwrap();
+        assert_eq!(table_columns(&args), [Column::Perms, Column::Size, Column::Owner, Column::Group,
+            Column::Atime, Column::Mtime, Column::Hash, Column::Name]);
+        assert_eq!(wanted_times(&args), [TimeKind::Accessed, TimeKind::Modified]);
+
+        let args = Args::try_parse_from(["treescan", "--columns", "name,ctime,size"]).unwrap();
+        assert_eq!(table_columns(&args), [Column::Name, Column::Ctime, Column::Size]);
+        assert_eq!(wanted_times(&args), [TimeKind::Changed]);
+
+        let args = Args::try_parse_from(["treescan", "--show-inodes"]).unwrap();
+        assert_eq!(table_columns(&args)[..2], [Column::Inode, Column::Perms]);
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("64K"), Ok(64 * 1024));
+        assert_eq!(parse_size("10m"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("2G"), Ok(2 << 30));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("1.5M").is_err());
+        assert!(parse_size("99999999999T").is_err());
+        assert_eq!(parse_chunk("1M"), Ok(1 << 20));
+        assert!(parse_chunk("0").is_err());
+        assert!(parse_chunk("2G").is_err());
+        assert_eq!(parse_rate("1M"), Ok(1 << 20));
+        assert!(parse_rate("0").is_err());
+        assert!(Args::try_parse_from(["treescan", "--limit-rate", "0"]).is_err());
+        assert_eq!(parse_octal("0002"), Ok(0o2));
+        assert_eq!(parse_octal("4000"), Ok(0o4000));
+        assert!(parse_octal("8").is_err());
+        assert!(parse_octal("17777").is_err());
+    }
+
+    #[test]
+    fn times() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1656676800);
+        let at = |secs| Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(when_at("2022-07-01T12:00:00Z", now), at(1656676800));
+        assert_eq!(when_at("2022-07-01T14:00:00+02:00", now), at(1656676800));
+        assert_eq!(when_at("2022-07-01 12:00", now), at(1656676800));
+        assert_eq!(when_at("2022-07-01", now), at(1656633600));
+        assert_eq!(when_at("90m", now), at(1656676800 - 5400));
+        assert_eq!(when_at("2D", now), at(1656676800 - 2 * 86400));
+        assert!(when_at("", now).is_err());
+        assert!(when_at("2y", now).is_err());
+        assert!(when_at("yesterday", now).is_err());
+        assert!(when_at("99999999999999w", now).is_err());
+    }
 
-    // Hash of empty string
     #[test]
-    fn md5() {
-        let x = md5::Context::new();
-        assert_eq!("d41d8cd98f00b204e9800998ecf8427e", format!("{:?}", x.compute()));
+    fn human_sizes() {
+        assert_eq!(human_size(0), "0");
+        assert_eq!(human_size(1023), "1023");
+        assert_eq!(human_size(1024), "1.0K");
+        assert_eq!(human_size(1536), "1.5K");
+        assert_eq!(human_size(10 * 1024), "10K");
+        assert_eq!(human_size(1048575), "1.0M");
+        assert_eq!(human_size(1048576), "1.0M");
+        assert_eq!(human_size(340 * 1024 * 1024), "340M");
+        assert_eq!(human_size(4402341478), "4.1G");
+        assert_eq!(human_size(u64::MAX), "16E");
     }
 
 }
@@ -0,0 +1,221 @@
+// Scan results as a Parquet file, one row per entry, with sizes, ids
+// and times as typed columns, written out a row group at a time.
+
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use ::parquet::basic::Compression;
+use ::parquet::data_type::{ByteArray, ByteArrayType, DataType, Int32Type, Int64Type};
+use ::parquet::errors::Result;
+use ::parquet::file::properties::WriterProperties;
+use ::parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+use ::parquet::schema::parser::parse_message_type;
+
+use crate::ScanEntry;
+
+
+/// Rows per row group.
+const BATCH: usize = 10_000;
+
+const SCHEMA: &str = "
+    message entries {
+        REQUIRED BYTE_ARRAY path (STRING);
+        REQUIRED INT64 size (INTEGER(64, false));
+        OPTIONAL BYTE_ARRAY mode (STRING);
+        OPTIONAL INT32 uid (INTEGER(32, false));
+        OPTIONAL INT32 gid (INTEGER(32, false));
+        OPTIONAL INT64 mtime (TIMESTAMP(MILLIS, true));
+        OPTIONAL BYTE_ARRAY hash (STRING);
+        REQUIRED BYTE_ARRAY kind (STRING);
+        OPTIONAL BYTE_ARRAY target (STRING);
+        OPTIONAL BYTE_ARRAY error (STRING);
+    }
+";
+
+
+/// One column's values for the rows not yet written, with a definition
+/// level per row: 1 for a value, 0 for a null.
+struct Column<T> {
+    values: Vec<T>,
+    levels: Vec<i16>,
+}
+
+
+impl<T> Column<T> {
+    fn push(&mut self, value: Option<T>) {
+        self.levels.push(value.is_some() as i16);
+        self.values.extend(value);
+    }
+
+
+    fn clear(&mut self) {
+        self.values.clear();
+        self.levels.clear();
+    }
+}
+
+
+impl<T> Default for Column<T> {
+    fn default() -> Self {
+        Self { values: Vec::new(), levels: Vec::new() }
+    }
+}
+
+
+#[derive(Default)]
+struct Rows {
+    path: Column<ByteArray>,
+    size: Column<i64>,
+    mode: Column<ByteArray>,
+    uid: Column<i32>,
+    gid: Column<i32>,
+    mtime: Column<i64>,
+    hash: Column<ByteArray>,
+    kind: Column<ByteArray>,
+    target: Column<ByteArray>,
+    error: Column<ByteArray>,
+}
+
+
+pub struct Writer {
+    file: SerializedFileWriter<File>,
+    rows: Rows,
+}
+
+
+impl Writer {
+    /// Start a fresh file at `path`, replacing any file already there.
+    pub fn create(path: &Path) -> Result<Self> {
+        let schema = Arc::new(parse_message_type(SCHEMA)?);
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let file = SerializedFileWriter::new(File::create(path)?, schema, Arc::new(props))?;
+        Ok(Self { file, rows: Rows::default() })
+    }
+
+
+    pub fn insert(&mut self, entry: &ScanEntry) -> Result<()> {
+        let text = |s: &str| ByteArray::from(s);
+        let rows = &mut self.rows;
+        rows.path.push(Some(text(&entry.path)));
+        rows.size.push(Some(entry.size as i64));
+        rows.mode.push(entry.mode.as_deref().map(text));
+        // unsigned in the schema, so the bits carry over as they are
+        rows.uid.push(entry.uid.map(|uid| uid as i32));
+        rows.gid.push(entry.gid.map(|gid| gid as i32));
+        rows.mtime.push(entry.mtime_unix.map(|secs| secs * 1000));
+        // unhashed files have a placeholder in the table, but null reads better here
+        rows.hash.push(Some(text(&entry.hash)).filter(|_| entry.has_digest()));
+        rows.kind.push(Some(text(entry.kind.as_str())));
+        rows.target.push(entry.target.as_deref().map(text));
+        rows.error.push(entry.error.as_deref().map(text));
+
+        if rows.path.levels.len() >= BATCH {
+            self.write_group()?;
+        }
+        Ok(())
+    }
+
+
+    /// Write whatever's left and the footer.
+    pub fn finish(mut self) -> Result<()> {
+        if !self.rows.path.levels.is_empty() {
+            self.write_group()?;
+        }
+        self.file.close()?;
+        Ok(())
+    }
+
+
+    /// Write the pending rows as a row group, in the schema's column order.
+    fn write_group(&mut self) -> Result<()> {
+        let rows = &mut self.rows;
+        let mut group = self.file.next_row_group()?;
+        write_column::<ByteArrayType>(&mut group, &mut rows.path)?;
+        write_column::<Int64Type>(&mut group, &mut rows.size)?;
+        write_column::<ByteArrayType>(&mut group, &mut rows.mode)?;
+        write_column::<Int32Type>(&mut group, &mut rows.uid)?;
+        write_column::<Int32Type>(&mut group, &mut rows.gid)?;
+        write_column::<Int64Type>(&mut group, &mut rows.mtime)?;
+        write_column::<ByteArrayType>(&mut group, &mut rows.hash)?;
+        write_column::<ByteArrayType>(&mut group, &mut rows.kind)?;
+        write_column::<ByteArrayType>(&mut group, &mut rows.target)?;
+        write_column::<ByteArrayType>(&mut group, &mut rows.error)?;
+        group.close()?;
+        Ok(())
+    }
+}
+
+
+fn write_column<T: DataType>(group: &mut SerializedRowGroupWriter<File>, column: &mut Column<T::T>) -> Result<()> {
+    let mut writer = group.next_column()?.expect("a column for each in the schema");
+    // the levels go unused for required columns, but they're all 1 there anyway
+    writer.typed::<T>().write_batch(&column.values, Some(&column.levels), None)?;
+    writer.close()?;
+    column.clear();
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{scan_path, ScanOptions};
+    use ::parquet::file::reader::{FileReader, SerializedFileReader};
+    use ::parquet::record::RowAccessor;
+
+    #[test]
+    fn rows() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("tree")).unwrap();
+        std::fs::write(tmp.path().join("tree/a"), "hello\n").unwrap();
+        std::fs::write(tmp.path().join("tree/b"), "").unwrap();
+
+        let out = tmp.path().join("scan.parquet");
+        std::fs::write(&out, "not parquet").unwrap();
+        let mut writer = Writer::create(&out).unwrap();
+        let entries: Vec<ScanEntry> = scan_path(&tmp.path().join("tree"), &ScanOptions::default()).unwrap().collect();
+        for entry in &entries {
+            writer.insert(entry).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let reader = SerializedFileReader::new(File::open(&out).unwrap()).unwrap();
+        let rows: Vec<_> = reader.get_row_iter(None).unwrap().map(|row| row.unwrap()).collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].get_string(0).unwrap(), "a");
+        assert_eq!(rows[0].get_ulong(1).unwrap(), 6);
+        assert_eq!(rows[0].get_string(6).unwrap(), "b1946ac9");
+        assert_eq!(rows[0].get_timestamp_millis(5).unwrap(), entries[0].mtime_unix.unwrap() * 1000);
+        #[cfg(unix)]
+        assert_eq!(rows[0].get_uint(3).unwrap(), entries[0].uid.unwrap());
+        assert_eq!(rows[1].get_string(0).unwrap(), "b");
+        assert_eq!(rows[1].get_ulong(1).unwrap(), 0);
+        assert!(rows[1].get_string(6).is_err());
+        assert!(rows[1].get_string(9).is_err());
+    }
+
+    #[test]
+    fn row_groups() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("f"), "hello\n").unwrap();
+        let entry = scan_path(tmp.path(), &ScanOptions::default()).unwrap().next().unwrap();
+
+        let out = tmp.path().join("scan.parquet");
+        let mut writer = Writer::create(&out).unwrap();
+        for _ in 0..BATCH + 1 {
+            writer.insert(&entry).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let reader = SerializedFileReader::new(File::open(&out).unwrap()).unwrap();
+        let groups: Vec<i64> = reader.metadata().row_groups().iter().map(|group| group.num_rows()).collect();
+        assert_eq!(groups, [BATCH as i64, 1]);
+    }
+
+}
+
+
+// EOF
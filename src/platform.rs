@@ -0,0 +1,431 @@
+// Platform-specific metadata access.  Unix exposes device numbers,
+// ownership and mode bits directly; Windows gets workable substitutes
+// so sizes, timestamps and hashes still come out right.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+
+#[cfg(unix)]
+mod imp {
+    use std::borrow::Cow;
+    use std::fs::Metadata;
+    use std::io;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    // getpwent() and getgrent() walk a single cursor held in libc and
+    // return pointers into static storage, so only one walk may run at
+    // a time across the whole process.
+    static ENT_LOCK: Mutex<()> = Mutex::new(());
+
+    pub fn file_device(_path: &Path, meta: &Metadata) -> u64 {
+        meta.dev()
+    }
+
+    pub fn file_id(_path: &Path, meta: &Metadata) -> (u64, u64) {
+        (meta.dev(), meta.ino())
+    }
+
+    pub fn link_count(meta: &Metadata) -> u64 {
+        meta.nlink()
+    }
+
+    pub fn inode(meta: &Metadata) -> Option<u64> {
+        Some(meta.ino())
+    }
+
+    pub fn allocated_size(meta: &Metadata) -> Option<u64> {
+        // st_blocks is always in 512-byte units, whatever the block size
+        Some(meta.blocks() * 512)
+    }
+
+    pub fn change_time(meta: &Metadata) -> Option<SystemTime> {
+        let secs = Duration::new(meta.ctime().unsigned_abs(), meta.ctime_nsec() as u32);
+        if meta.ctime() >= 0 {
+            UNIX_EPOCH.checked_add(secs)
+        }
+        else {
+            UNIX_EPOCH.checked_sub(secs)
+        }
+    }
+
+    pub fn file_owner(meta: &Metadata) -> Option<(u32, u32)> {
+        Some((meta.uid(), meta.gid()))
+    }
+
+    pub fn file_mode(meta: &Metadata) -> u32 {
+        meta.permissions().mode()
+    }
+
+    pub fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+        use std::os::unix::ffi::OsStrExt;
+        Cow::Borrowed(path.as_os_str().as_bytes())
+    }
+
+    pub fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+        use std::os::unix::ffi::OsStringExt;
+        PathBuf::from(std::ffi::OsString::from_vec(bytes))
+    }
+
+    pub fn xattrs(path: &Path) -> io::Result<Vec<(String, Vec<u8>)>> {
+        let mut attrs = Vec::new();
+        for name in xattr::list(path)? {
+            if let Some(value) = xattr::get(path, &name)? {
+                attrs.push((name.to_string_lossy().into_owned(), value));
+            }
+        }
+        Ok(attrs)
+    }
+
+    pub fn xattr(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+        xattr::get(path, name)
+    }
+
+    pub fn user_name(uid: u32) -> Option<String> {
+        users::get_user_by_uid(uid).map(|u| u.name().to_string_lossy().into_owned())
+    }
+
+    pub fn group_name(gid: u32) -> Option<String> {
+        users::get_group_by_gid(gid).map(|g| g.name().to_string_lossy().into_owned())
+    }
+
+    pub fn all_users() -> Vec<(u32, String)> {
+        let _walk = ENT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // SAFETY: ENT_LOCK is held until the iterator, which calls
+        // getpwent() and endpwent() on drop, is used up and dropped.
+        let users = unsafe { users::all_users() }
+            .map(|u| (u.uid(), u.name().to_string_lossy().into_owned()))
+            .collect();
+        users
+    }
+
+    pub fn all_groups() -> Vec<(u32, String)> {
+        let mut groups = Vec::new();
+        let _walk = ENT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // SAFETY: ENT_LOCK keeps other walks out until endgrent(), and
+        // each entry is copied out before the next call reuses its storage.
+        unsafe {
+            libc::setgrent();
+            loop {
+                let group = libc::getgrent();
+                if group.is_null() {
+                    break;
+                }
+                let name = std::ffi::CStr::from_ptr((*group).gr_name);
+                groups.push(((*group).gr_gid, name.to_string_lossy().into_owned()));
+            }
+            libc::endgrent();
+        }
+        groups
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn mounts() -> Vec<(u64, String, String)> {
+        let text = std::fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+        super::parse_mountinfo(&text).into_iter()
+            .map(|(major, minor, fstype, source)| (libc::makedev(major, minor), fstype, source))
+            .collect()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn mounts() -> Vec<(u64, String, String)> {
+        Vec::new()
+    }
+}
+
+
+#[cfg(windows)]
+mod imp {
+    use std::borrow::Cow;
+    use std::collections::hash_map::DefaultHasher;
+    use std::fs::Metadata;
+    use std::hash::{Hash, Hasher};
+    use std::io;
+    use std::path::{Component, Path, PathBuf, Prefix};
+    use std::time::SystemTime;
+
+    // No device numbers here, so treat each drive letter (or UNC share)
+    // as its own filesystem.
+    pub fn file_device(path: &Path, _meta: &Metadata) -> u64 {
+        let full = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        match full.components().next() {
+            Some(Component::Prefix(prefix)) => match prefix.kind() {
+                Prefix::Disk(letter) | Prefix::VerbatimDisk(letter) => letter.to_ascii_uppercase() as u64,
+                other => {
+                    let mut hasher = DefaultHasher::new();
+                    format!("{:?}", other).hash(&mut hasher);
+                    hasher.finish()
+                }
+            },
+            _ => 0,
+        }
+    }
+
+    // File indexes aren't available on stable, so fall back to the
+    // canonical path.
+    pub fn file_id(path: &Path, meta: &Metadata) -> (u64, u64) {
+        let full = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let mut hasher = DefaultHasher::new();
+        full.hash(&mut hasher);
+        (file_device(path, meta), hasher.finish())
+    }
+
+    // The link count needs an open handle; report every file as unshared.
+    pub fn link_count(_meta: &Metadata) -> u64 {
+        1
+    }
+
+    // The file index needs an open handle too.
+    pub fn inode(_meta: &Metadata) -> Option<u64> {
+        None
+    }
+
+    // Needs GetCompressedFileSize, which std doesn't wrap.
+    pub fn allocated_size(_meta: &Metadata) -> Option<u64> {
+        None
+    }
+
+    // NTFS keeps a change time but std doesn't expose it.
+    pub fn change_time(_meta: &Metadata) -> Option<SystemTime> {
+        None
+    }
+
+    pub fn file_owner(_meta: &Metadata) -> Option<(u32, u32)> {
+        None
+    }
+
+    // Synthesize unix-style bits: there is only a read-only flag.
+    pub fn file_mode(meta: &Metadata) -> u32 {
+        let kind = if meta.file_type().is_symlink() { 0o120000 }
+            else if meta.is_dir() { 0o040000 }
+            else { 0o100000 };
+        let bits = if meta.permissions().readonly() { 0o444 } else { 0o666 };
+        kind | bits
+    }
+
+    // Paths are UTF-16 underneath, so there's no exact byte form.
+    pub fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+        match path.to_string_lossy() {
+            Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+            Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+        }
+    }
+
+    pub fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+        PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    // Alternate data streams are the nearest thing, and std can't list them.
+    pub fn xattrs(_path: &Path) -> io::Result<Vec<(String, Vec<u8>)>> {
+        Ok(Vec::new())
+    }
+
+    pub fn xattr(_path: &Path, _name: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    pub fn user_name(_uid: u32) -> Option<String> {
+        None
+    }
+
+    pub fn group_name(_gid: u32) -> Option<String> {
+        None
+    }
+
+    pub fn all_users() -> Vec<(u32, String)> {
+        Vec::new()
+    }
+
+    pub fn all_groups() -> Vec<(u32, String)> {
+        Vec::new()
+    }
+
+    pub fn mounts() -> Vec<(u64, String, String)> {
+        Vec::new()
+    }
+}
+
+
+/// Identifies the filesystem holding `path`, for same-filesystem checks.
+pub fn file_device(path: &Path, meta: &Metadata) -> u64 {
+    imp::file_device(path, meta)
+}
+
+
+/// (device, inode) or the nearest equivalent: equal for two paths to
+/// the same file.
+pub fn file_id(path: &Path, meta: &Metadata) -> (u64, u64) {
+    imp::file_id(path, meta)
+}
+
+
+/// Number of hard links to the file.
+pub fn link_count(meta: &Metadata) -> u64 {
+    imp::link_count(meta)
+}
+
+
+/// The inode number, where the platform has one.
+pub fn inode(meta: &Metadata) -> Option<u64> {
+    imp::inode(meta)
+}
+
+
+/// Bytes allocated on disk, which differs from the length for sparse
+/// files and block rounding.
+pub fn allocated_size(meta: &Metadata) -> Option<u64> {
+    imp::allocated_size(meta)
+}
+
+
+/// When the inode last changed (`st_ctime`), where available.
+pub fn change_time(meta: &Metadata) -> Option<SystemTime> {
+    imp::change_time(meta)
+}
+
+
+/// Numeric (uid, gid), where the platform has such a thing.
+pub fn file_owner(meta: &Metadata) -> Option<(u32, u32)> {
+    imp::file_owner(meta)
+}
+
+
+/// Unix `st_mode`: file type and permission bits.
+pub fn file_mode(meta: &Metadata) -> u32 {
+    imp::file_mode(meta)
+}
+
+
+/// Permissions rendered like `ls -l`, e.g. `-rwxr-xr-x`.
+pub fn file_mode_string(meta: &Metadata) -> String {
+    unix_mode::to_string(file_mode(meta))
+}
+
+
+/// The path as raw bytes, for output that has to round-trip.
+pub fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    imp::path_bytes(path)
+}
+
+
+/// The reverse of `path_bytes()`.
+pub fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    imp::path_from_bytes(bytes)
+}
+
+
+/// Extended attributes of `path` itself (not a symlink's target), as
+/// (name, value) pairs.
+pub fn xattrs(path: &Path) -> io::Result<Vec<(String, Vec<u8>)>> {
+    imp::xattrs(path)
+}
+
+
+/// One extended attribute of `path` itself, if it's set.
+pub fn xattr(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+    imp::xattr(path, name)
+}
+
+
+pub fn user_name(uid: u32) -> Option<String> {
+    imp::user_name(uid)
+}
+
+
+pub fn group_name(gid: u32) -> Option<String> {
+    imp::group_name(gid)
+}
+
+
+/// Every user the system knows of, as (uid, name), read in one pass;
+/// not thread-safe, so only call it before starting any threads.
+pub fn all_users() -> Vec<(u32, String)> {
+    imp::all_users()
+}
+
+
+/// Every group, likewise, as (gid, name).
+pub fn all_groups() -> Vec<(u32, String)> {
+    imp::all_groups()
+}
+
+
+/// Each mounted filesystem's type and source, as `TYPE on SOURCE`, by
+/// device number (the first mount of each, where one is mounted twice).
+/// Only Linux says; elsewhere this is empty.
+pub fn mounts() -> HashMap<u64, String> {
+    let mut mounts = HashMap::new();
+    for (dev, fstype, source) in imp::mounts() {
+        mounts.entry(dev).or_insert_with(|| format!("{} on {}", fstype, source));
+    }
+    mounts
+}
+
+
+/// The major and minor device numbers, type and source of each line of
+/// a `/proc/PID/mountinfo`.  Lines that don't parse are skipped.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_mountinfo(text: &str) -> Vec<(u32, u32, String, String)> {
+    text.lines().filter_map(|line| {
+        // ID PARENT MAJOR:MINOR ROOT POINT OPTIONS [OPTIONAL...] - TYPE SOURCE SUPER
+        let (fixed, rest) = line.split_once(" - ")?;
+        let (major, minor) = fixed.split(' ').nth(2)?.split_once(':')?;
+        let mut rest = rest.split(' ');
+        let fstype = rest.next()?;
+        let source = rest.next()?;
+        Some((major.parse().ok()?, minor.parse().ok()?, unescape_mount(fstype), unescape_mount(source)))
+    }).collect()
+}
+
+
+/// Undo mountinfo's octal escapes, `\040` for a space and so on.
+fn unescape_mount(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes.get(i + 1..i + 4)
+            .filter(|digits| bytes[i] == b'\\' && digits.iter().all(|d| (b'0'..=b'7').contains(d)))
+            .map(|digits| digits.iter().fold(0u32, |n, d| n * 8 + (d - b'0') as u32));
+        match octal {
+            Some(byte) if byte <= 0xff => {
+                out.push(byte as u8);
+                i += 4;
+            }
+            _ => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mountinfo() {
+        let text = "22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw\n\
+                    40 22 0:35 / /mnt/my\\040disk rw - fuse.sshfs me@host:/my\\040files rw\n\
+                    41 22 0:36 / /broken\n";
+        assert_eq!(parse_mountinfo(text), [
+            (8, 1, "ext4".to_string(), "/dev/sda1".to_string()),
+            (0, 35, "fuse.sshfs".into(), "me@host:/my files".into()),
+        ]);
+        assert_eq!(unescape_mount(r"a\134b\0"), r"a\b\0");
+    }
+
+}
+
+
+// EOF
@@ -0,0 +1,238 @@
+// Reading directory listings ahead of the walk.  The Scanner still
+// visits one directory at a time, in order, but the listings it's
+// about to need are read and sorted on worker threads meanwhile, so
+// slow filesystems and wide trees keep several reads in flight.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+
+use walkdir::{DirEntry, WalkDir};
+
+use crate::SortKey;
+
+
+/// A directory's entries, sorted, as the walk yields them.
+pub type Listing = Vec<walkdir::Result<DirEntry>>;
+
+
+/// How listings are read: the same for every directory in a scan.
+#[derive(Clone, Copy, Debug)]
+pub struct Lister {
+    pub sort: SortKey,
+    /// Largest, newest or last by name first instead.
+    pub reverse: bool,
+    pub cross_device: bool,
+}
+
+
+impl Lister {
+    /// Read `dir`'s immediate entries, in order.  Keys other than the
+    /// name fall back to it so the order stays deterministic.
+    pub fn list(&self, dir: &Path) -> Listing {
+        let walk = WalkDir::new(dir)
+            .min_depth(1)
+            .max_depth(1)
+            .same_file_system(!self.cross_device);
+
+        let (key, reverse) = (self.sort, self.reverse);
+        let walk = match key {
+            SortKey::None => walk,
+            _ => walk.sort_by(move |a, b| {
+                let order = match key {
+                    SortKey::Size => size_key(a).cmp(&size_key(b)),
+                    SortKey::Mtime => mtime_key(a).cmp(&mtime_key(b)),
+                    SortKey::Name | SortKey::None => Ordering::Equal,
+                }.then_with(|| a.file_name().cmp(b.file_name()));
+                if reverse { order.reverse() } else { order }
+            }),
+        };
+        walk.into_iter().collect()
+    }
+}
+
+
+/// Size as reported, so directories sort as empty.
+fn size_key(entry: &DirEntry) -> u64 {
+    if entry.file_type().is_dir() {
+        return 0;
+    }
+    entry.metadata().map(|meta| meta.len()).unwrap_or(0)
+}
+
+
+fn mtime_key(entry: &DirEntry) -> Option<std::time::SystemTime> {
+    entry.metadata().ok()?.modified().ok()
+}
+
+
+/// The directories asked for and not yet taken or cancelled, and those
+/// of their listings read so far.
+#[derive(Default)]
+struct State {
+    pending: HashSet<PathBuf>,
+    listings: HashMap<PathBuf, Listing>,
+}
+
+
+/// The state shared with the workers, and a signal for each new listing.
+type Shared = Arc<(Mutex<State>, Condvar)>;
+
+
+/// Worker threads reading the listings asked for.
+pub struct Prefetch {
+    lister: Lister,
+    jobs: Option<Sender<PathBuf>>,
+    shared: Shared,
+    workers: Vec<JoinHandle<()>>,
+}
+
+
+impl Prefetch {
+    pub fn new(lister: Lister, threads: usize) -> Self {
+        let (jobs, queue) = mpsc::channel::<PathBuf>();
+        let queue = Arc::new(Mutex::new(queue));
+        let shared: Shared = Arc::new((Mutex::new(State::default()), Condvar::new()));
+
+        let workers = (0..threads).map(|_| {
+            let queue = Arc::clone(&queue);
+            let shared = Arc::clone(&shared);
+            std::thread::spawn(move || loop {
+                // the lock is only held while waiting for the next job
+                let next = queue.lock().unwrap().recv();
+                let dir = match next {
+                    Ok(dir) => dir,
+                    Err(_) => break,
+                };
+                let (lock, signal) = &*shared;
+                // cancelled while it waited
+                if !lock.lock().unwrap().pending.contains(&dir) {
+                    continue;
+                }
+                let listing = lister.list(&dir);
+                let mut state = lock.lock().unwrap();
+                // or while it was read
+                if state.pending.contains(&dir) {
+                    state.listings.insert(dir, listing);
+                    signal.notify_all();
+                }
+            })
+        }).collect();
+
+        Self { lister, jobs: Some(jobs), shared, workers }
+    }
+
+
+    /// Start reading `dir` in the background, unless it's already asked for.
+    pub fn request(&mut self, dir: &Path) {
+        let (lock, _) = &*self.shared;
+        if lock.lock().unwrap().pending.insert(dir.to_path_buf()) {
+            if let Some(jobs) = &self.jobs {
+                // the workers only go once this is dropped
+                let _ = jobs.send(dir.to_path_buf());
+            }
+        }
+    }
+
+
+    /// How many listings are asked for and not yet taken.
+    pub fn outstanding(&self) -> usize {
+        self.shared.0.lock().unwrap().pending.len()
+    }
+
+
+    /// Forget `dir`'s listing, read or not, now it won't be taken.
+    pub fn cancel(&mut self, dir: &Path) {
+        let mut state = self.shared.0.lock().unwrap();
+        state.pending.remove(dir);
+        state.listings.remove(dir);
+    }
+
+
+    /// `dir`'s listing: waiting for it if it was asked for, otherwise
+    /// reading it now.
+    pub fn take(&mut self, dir: &Path) -> Listing {
+        let (lock, signal) = &*self.shared;
+        let mut state = lock.lock().unwrap();
+        if !state.pending.contains(dir) {
+            drop(state);
+            return self.lister.list(dir);
+        }
+
+        loop {
+            if let Some(listing) = state.listings.remove(dir) {
+                state.pending.remove(dir);
+                return listing;
+            }
+            state = signal.wait(state).unwrap();
+        }
+    }
+}
+
+
+impl Drop for Prefetch {
+    fn drop(&mut self) {
+        // closing the queue lets the workers finish what they're on and stop
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn listings() {
+        let tmp = tempfile::tempdir().unwrap();
+        for dir in ["a", "b", "c"] {
+            std::fs::create_dir(tmp.path().join(dir)).unwrap();
+            for name in ["z", "y", "x"] {
+                std::fs::write(tmp.path().join(dir).join(name), "").unwrap();
+            }
+        }
+
+        let lister = Lister { sort: SortKey::Name, reverse: false, cross_device: false };
+        let names = |listing: Listing| -> Vec<String> {
+            listing.into_iter().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect()
+        };
+
+        let mut prefetch = Prefetch::new(lister, 2);
+        prefetch.request(&tmp.path().join("a"));
+        prefetch.request(&tmp.path().join("b"));
+        prefetch.request(&tmp.path().join("a"));
+        assert_eq!(prefetch.outstanding(), 2);
+        assert_eq!(names(prefetch.take(&tmp.path().join("b"))), ["x", "y", "z"]);
+        // never asked for: read in line
+        assert_eq!(names(prefetch.take(&tmp.path().join("c"))), ["x", "y", "z"]);
+        assert_eq!(names(prefetch.take(&tmp.path().join("a"))), ["x", "y", "z"]);
+        assert_eq!(prefetch.outstanding(), 0);
+
+        // cancelled whether it's been read yet or not, and not kept when it is
+        prefetch.request(&tmp.path().join("a"));
+        prefetch.request(&tmp.path().join("b"));
+        prefetch.cancel(&tmp.path().join("a"));
+        assert_eq!(prefetch.outstanding(), 1);
+        assert_eq!(names(prefetch.take(&tmp.path().join("b"))), ["x", "y", "z"]);
+        assert_eq!(prefetch.outstanding(), 0);
+        // let the workers get past "a", then nothing was left behind for it
+        drop(prefetch.jobs.take());
+        for worker in prefetch.workers.drain(..) {
+            worker.join().unwrap();
+        }
+        assert!(prefetch.shared.0.lock().unwrap().listings.is_empty());
+
+        let reversed = Lister { reverse: true, ..lister };
+        assert_eq!(names(reversed.list(&tmp.path().join("a"))), ["z", "y", "x"]);
+    }
+
+}
+
+
+// EOF
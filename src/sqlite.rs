@@ -0,0 +1,106 @@
+// Scan results as an SQLite database, one `entries` row per entry,
+// inserted in batched transactions.
+
+use std::path::Path;
+
+use rusqlite::{params, Connection};
+
+use crate::ScanEntry;
+
+
+/// Rows per transaction.
+const BATCH: usize = 10_000;
+
+
+pub struct Writer {
+    conn: Connection,
+    /// Rows inserted since the last commit.
+    pending: usize,
+}
+
+
+impl Writer {
+    /// Start a fresh database at `path`, replacing any file already there.
+    pub fn create(path: &Path) -> rusqlite::Result<Self> {
+        // if this fails, so will creating the table, with a clearer error
+        let _ = std::fs::remove_file(path);
+
+        let conn = Connection::open(path)?;
+        conn.execute_batch("
+            CREATE TABLE entries (
+                path TEXT, size INTEGER, mode TEXT, uid INTEGER, gid INTEGER,
+                mtime INTEGER, hash TEXT, kind TEXT, target TEXT, error TEXT
+            );
+            BEGIN;
+        ")?;
+        Ok(Self { conn, pending: 0 })
+    }
+
+
+    pub fn insert(&mut self, entry: &ScanEntry) -> rusqlite::Result<()> {
+        let mut insert = self.conn.prepare_cached(
+            "INSERT INTO entries VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)")?;
+        // unhashed files have a placeholder in the table, but NULL reads better here
+        let hash = Some(entry.hash.as_str()).filter(|_| entry.has_digest());
+        insert.execute(params![
+            entry.path,
+            entry.size as i64,
+            entry.mode,
+            entry.uid,
+            entry.gid,
+            entry.mtime_unix,
+            hash,
+            entry.kind.as_str(),
+            entry.target,
+            entry.error,
+        ])?;
+        drop(insert);
+
+        self.pending += 1;
+        if self.pending >= BATCH {
+            self.conn.execute_batch("COMMIT; BEGIN;")?;
+            self.pending = 0;
+        }
+        Ok(())
+    }
+
+
+    /// Commit whatever's left.
+    pub fn finish(self) -> rusqlite::Result<()> {
+        self.conn.execute_batch("COMMIT;")
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{scan_path, ScanOptions};
+
+    #[test]
+    fn rows() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("tree")).unwrap();
+        std::fs::write(tmp.path().join("tree/a"), "hello\n").unwrap();
+        std::fs::write(tmp.path().join("tree/b"), "").unwrap();
+
+        let db = tmp.path().join("scan.db");
+        std::fs::write(&db, "not a database").unwrap();
+        let mut writer = Writer::create(&db).unwrap();
+        for entry in scan_path(&tmp.path().join("tree"), &ScanOptions::default()).unwrap() {
+            writer.insert(&entry).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let conn = Connection::open(&db).unwrap();
+        let rows: Vec<(String, i64, Option<String>)> = conn
+            .prepare("SELECT path, size, hash FROM entries ORDER BY path").unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        assert_eq!(rows, [("a".into(), 6, Some("b1946ac9".into())), ("b".into(), 0, None)]);
+    }
+
+}
+
+
+// EOF
@@ -0,0 +1,295 @@
+// Checking a scan against a manifest of expected digests: either an
+// earlier `--format json` (or ndjson) scan, or a checksum list as
+// written by `md5sum`, `sha256sum` and friends, plain or `--tag`ged.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use crate::{HashAlgo, ScanEntry};
+
+
+/// The result of checking one manifest path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Ok,
+    /// Found, but with different contents or not hashed at all.
+    Mismatch,
+    /// Listed in the manifest but not found by the scan.
+    Missing,
+}
+
+
+impl Verdict {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Verdict::Ok => "OK",
+            Verdict::Mismatch => "MISMATCH",
+            Verdict::Missing => "MISSING",
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// A line that's neither `HASH  PATH` nor `ALGO (PATH) = HASH`.
+    Syntax(usize),
+    /// The manifest's digests can't have come from the chosen algorithm.
+    Algo(String),
+}
+
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Json(e) => write!(f, "{}", e),
+            Error::Syntax(line) => write!(f, "line {}: not a checksum line", line),
+            Error::Algo(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+
+impl std::error::Error for Error {}
+
+
+/// Expected digests by path relative to the root, in manifest order,
+/// noting which paths the scan has found.
+pub struct Manifest {
+    entries: Vec<(String, String)>,
+    index: HashMap<String, usize>,
+    seen: Vec<bool>,
+}
+
+
+impl Manifest {
+    pub fn load(path: &Path, algo: HashAlgo) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path).map_err(Error::Io)?, algo)
+    }
+
+
+    /// Parse a JSON scan or a checksum list, and check its digests
+    /// suit `algo`.  A scan's digests may be truncated (see
+    /// `--hashlen`); a checksum list's must be whole.
+    pub fn parse(text: &str, algo: HashAlgo) -> Result<Self, Error> {
+        let start = text.trim_start();
+        let entries = if start.starts_with('[') || start.starts_with('{') {
+            from_scan(text)?
+        }
+        else {
+            from_sums(text, algo)?
+        };
+
+        let len = algo.digest_len();
+        if let Some((path, _)) = entries.iter().find(|(_, digest)| digest.len() > len) {
+            return Err(Error::Algo(format!("{}: digest is too long for {:?}", path, algo)));
+        }
+
+        let index = entries.iter().enumerate()
+            .map(|(i, (path, _))| (path.clone(), i))
+            .collect();
+        let seen = vec![false; entries.len()];
+        Ok(Self { entries, index, seen })
+    }
+
+
+    /// Check `entry` against the manifest, or None if it isn't listed.
+    /// A digest matches if it starts with the manifest's.
+    pub fn check(&mut self, entry: &ScanEntry) -> Option<Verdict> {
+        let &i = self.index.get(&entry.path)?;
+        self.seen[i] = true;
+
+        let expected = &self.entries[i].1;
+        if entry.has_digest() && entry.hash.starts_with(expected.as_str()) {
+            Some(Verdict::Ok)
+        }
+        else {
+            Some(Verdict::Mismatch)
+        }
+    }
+
+
+    /// Manifest paths nothing in the scan matched, in manifest order.
+    pub fn missing(&self) -> impl Iterator<Item = &str> + '_ {
+        self.entries.iter().zip(&self.seen)
+            .filter(|(_, seen)| !**seen)
+            .map(|(entry, _)| entry.0.as_str())
+    }
+}
+
+
+/// Paths and digests from a JSON scan, skipping entries without one.
+fn from_scan(text: &str) -> Result<Vec<(String, String)>, Error> {
+    let entries: Vec<ScanEntry> = if text.trim_start().starts_with('[') {
+        serde_json::from_str(text).map_err(Error::Json)?
+    }
+    else {
+        text.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()
+            .map_err(Error::Json)?
+    };
+
+    Ok(entries.into_iter()
+        .filter(ScanEntry::has_digest)
+        .map(|entry| (entry.path, entry.hash.to_ascii_lowercase()))
+        .collect())
+}
+
+
+/// Paths and digests from `HASH  PATH` lines (`*PATH` for binary mode),
+/// or `ALGO (PATH) = HASH` ones, which must name `algo`.  A leading `./`
+/// is dropped from paths, as `find .` would give them.
+fn from_sums(text: &str, algo: HashAlgo) -> Result<Vec<(String, String)>, Error> {
+    let mut entries = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // a leading backslash means the name has \\ and \n escapes
+        let (escaped, line) = match line.strip_prefix('\\') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+
+        let (path, digest) = match tagged(line) {
+            Some((name, path, digest)) => {
+                if !name.eq_ignore_ascii_case(algo_name(algo)) {
+                    return Err(Error::Algo(format!("line {}: {} digest, but --hash is {:?}",
+                        n + 1, name, algo)));
+                }
+                (path, digest)
+            }
+            None => {
+                let (digest, rest) = line.split_once(' ').ok_or(Error::Syntax(n + 1))?;
+                let path = rest.strip_prefix(' ').or_else(|| rest.strip_prefix('*'))
+                    .ok_or(Error::Syntax(n + 1))?;
+                (path, digest)
+            }
+        };
+
+        if digest.is_empty() || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(Error::Syntax(n + 1));
+        }
+        if digest.len() != algo.digest_len() {
+            return Err(Error::Algo(format!("line {}: {} hex digits, but {:?} has {}",
+                n + 1, digest.len(), algo, algo.digest_len())));
+        }
+
+        let path = if escaped { unescape(path) } else { path.to_string() };
+        let path = path.strip_prefix("./").map(String::from).unwrap_or(path);
+        entries.push((path, digest.to_ascii_lowercase()));
+    }
+    Ok(entries)
+}
+
+
+/// Split `ALGO (PATH) = HASH` into its parts.
+fn tagged(line: &str) -> Option<(&str, &str, &str)> {
+    let (name, rest) = line.split_once(" (")?;
+    let (path, digest) = rest.rsplit_once(") = ")?;
+    if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-') {
+        return None;
+    }
+    Some((name, path, digest))
+}
+
+
+/// The tag coreutils writes for each algorithm.
+fn algo_name(algo: HashAlgo) -> &'static str {
+    match algo {
+        HashAlgo::Md5 => "MD5",
+        HashAlgo::Sha1 => "SHA1",
+        HashAlgo::Sha256 => "SHA256",
+        HashAlgo::Blake3 => "BLAKE3",
+    }
+}
+
+
+/// A checksum list line, `HASH  PATH`, as `md5sum` writes it: names
+/// with `\` or newlines escaped, and the line marked with a leading `\`.
+pub fn sum_line(digest: &str, path: &str) -> String {
+    if !path.contains(['\\', '\n']) {
+        return format!("{}  {}", digest, path);
+    }
+    format!("\\{}  {}", digest, path.replace('\\', "\\\\").replace('\n', "\\n"))
+}
+
+
+/// Undo coreutils' escaping of `\` and newlines in names.
+fn unescape(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('\\')) => { out.push('\\'); chars.next(); }
+            ('\\', Some('n')) => { out.push('\n'); chars.next(); }
+            (c, _) => out.push(c),
+        }
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(path: &str, hash: &str) -> ScanEntry {
+        serde_json::from_value(serde_json::json!({
+            "path": path, "size": 0, "mode": null, "owner": "", "group": "",
+            "mtime": "", "hash": hash, "target": null, "kind": "file",
+        })).unwrap()
+    }
+
+    #[test]
+    fn checksum_lists() {
+        let text = "b1946ac92492d2347c6235b4d2611184  ./a\n\
+                    \n\
+                    D41D8CD98F00B204E9800998ECF8427E *b c\n\
+                    MD5 (sub/d) = d41d8cd98f00b204e9800998ecf8427e\n\
+                    \\d41d8cd98f00b204e9800998ecf8427e  two\\nlines\n";
+        let mut manifest = Manifest::parse(text, HashAlgo::Md5).unwrap();
+        assert_eq!(manifest.check(&entry("a", "b1946ac92492d2347c6235b4d2611184")), Some(Verdict::Ok));
+        assert_eq!(manifest.check(&entry("b c", "00000000000000000000000000000000")), Some(Verdict::Mismatch));
+        assert_eq!(manifest.check(&entry("two\nlines", "--------")), Some(Verdict::Mismatch));
+        assert_eq!(manifest.check(&entry("other", "")), None);
+        assert_eq!(manifest.missing().collect::<Vec<_>>(), ["sub/d"]);
+
+        assert!(matches!(Manifest::parse(text, HashAlgo::Sha256), Err(Error::Algo(_))));
+        assert!(matches!(Manifest::parse("SHA1 (a) = da39a3ee5e6b4b0d3255bfef95601890afd80709\n", HashAlgo::Md5),
+            Err(Error::Algo(_))));
+        assert!(matches!(Manifest::parse("nonsense\n", HashAlgo::Md5), Err(Error::Syntax(1))));
+
+        let digest = "d41d8cd98f00b204e9800998ecf8427e";
+        assert_eq!(sum_line(digest, "a b"), format!("{}  a b", digest));
+        let line = sum_line(digest, "two\nlines\\");
+        assert_eq!(line, format!("\\{}  two\\nlines\\\\", digest));
+        let mut manifest = Manifest::parse(&line, HashAlgo::Md5).unwrap();
+        assert_eq!(manifest.check(&entry("two\nlines\\", digest)), Some(Verdict::Ok));
+    }
+
+    #[test]
+    fn scans() {
+        let text = "{\"path\":\"a\",\"size\":6,\"mode\":null,\"owner\":\"\",\"group\":\"\",\"mtime\":\"\",\"hash\":\"b1946ac9\",\"target\":null,\"kind\":\"file\"}\n\
+                    {\"path\":\"d\",\"size\":0,\"mode\":null,\"owner\":\"\",\"group\":\"\",\"mtime\":\"\",\"hash\":\"\",\"target\":null,\"kind\":\"dir\"}\n";
+        let mut manifest = Manifest::parse(text, HashAlgo::Md5).unwrap();
+        // truncated digests match the front of the full one
+        assert_eq!(manifest.check(&entry("a", "b1946ac92492d2347c6235b4d2611184")), Some(Verdict::Ok));
+        // entries without a digest aren't checked
+        assert_eq!(manifest.check(&entry("d", "")), None);
+        assert_eq!(manifest.missing().count(), 0);
+    }
+
+}
+
+
+// EOF
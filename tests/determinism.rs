@@ -0,0 +1,62 @@
+// Two scans of an unchanged tree must print the same bytes, whatever
+// the thread count or the order the roots were given in.
+
+use std::path::Path;
+use std::process::Command;
+
+
+fn scan(args: &[&str], roots: &[&Path]) -> Vec<u8> {
+    let out = Command::new(env!("CARGO_BIN_EXE_treescan"))
+        .args(args)
+        .args(roots)
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    out.stdout
+}
+
+
+fn fixture(root: &Path) {
+    for (i, dir) in ["", "src", "src/deep", "docs", "empty"].iter().enumerate() {
+        let dir = root.join(dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for j in 0..5 {
+            let body = format!("file {} {}\n", i, j).repeat(j * 100);
+            std::fs::write(dir.join(format!("f{}.txt", j)), body).unwrap();
+        }
+    }
+    std::fs::write(root.join("empty/f0.txt"), "").unwrap();
+}
+
+
+#[test]
+fn repeat_scans_match() {
+    let tmp = tempfile::tempdir().unwrap();
+    fixture(tmp.path());
+    let root = [tmp.path()];
+
+    for format in ["table", "json", "csv"] {
+        let first = scan(&["--format", format, "--jobs", "1"], &root);
+        assert!(!first.is_empty());
+        assert_eq!(first, scan(&["--format", format, "--jobs", "1"], &root));
+        assert_eq!(first, scan(&["--format", format, "--jobs", "4"], &root));
+        assert_eq!(first, scan(&["--format", format, "--jobs", "1", "--threads", "4"], &root));
+    }
+}
+
+
+#[test]
+fn sorted_roots() {
+    let tmp = tempfile::tempdir().unwrap();
+    fixture(tmp.path());
+    let (a, b) = (tmp.path().join("docs"), tmp.path().join("src"));
+
+    let forward = scan(&["--sort-roots"], &[&a, &b]);
+    assert_eq!(forward, scan(&["--sort-roots"], &[&b, &a]));
+    assert_eq!(forward, scan(&[], &[&a, &b]));
+    assert_ne!(forward, scan(&[], &[&b, &a]));
+    assert_eq!(forward, scan(&["--root-jobs", "2"], &[&a, &b]));
+}
+
+
+// EOF